@@ -0,0 +1,70 @@
+//! Git merge driver for the table files
+//!
+//! Usage: rental_rod_merge <base> <ours> <theirs> [<path>]
+//!
+//! The lines are merged by id and field by field (see rental_rod::db::merge).
+//! The result is written to <ours>. The exit code is 0 when the merge is clean,
+//! and 1 when there are conflicts (they are written between conflict markers).
+//!
+//! With the path of the file in the repository, a table and its journal are merged
+//! as one table (see merge::merge_tracked_files). The files that are not tables
+//! (.gitignore, etc.) are merged by `git merge-file`, except the journals: they are
+//! never merged as text, a journal that cannot be merged is a conflict.
+//! Register the driver with Db::set_merge_driver.
+use std::process::{exit, Command};
+
+use rental_rod::db::merge;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        eprintln!("Usage: {} <base> <ours> <theirs> [<path>]", args[0]);
+        exit(2);
+    }
+
+    let (base, ours, theirs) = (&args[1], &args[2], &args[3]);
+    let merged = match args.get(4) {
+        Some(path) => merge::merge_tracked_files(base, ours, theirs, path),
+        None => merge::merge_files(base, ours, theirs)
+    };
+    match merged {
+        Ok(true) => exit(0),
+        Ok(false) => exit(1),
+        Err(error) if args.get(4).is_some_and(|path| path.starts_with('.') && path.ends_with(".journal")) => {
+            eprintln!("{}", error);
+            exit(1)
+        },
+        Err(_) => exit(merge_file(base, ours, theirs))
+    }
+}
+
+/**
+ * Fallback to the text merge of git
+ */
+fn merge_file(base: &str, ours: &str, theirs: &str) -> i32 {
+    let status = Command::new("git")
+        .arg("merge-file")
+        .arg("-L")
+        .arg("ours")
+        .arg("-L")
+        .arg("base")
+        .arg("-L")
+        .arg("theirs")
+        .arg(ours)
+        .arg(base)
+        .arg(theirs)
+        .status();
+
+    match status {
+        // git merge-file returns the number of conflicts (or a negative number on error)
+        Ok(status) => match status.code() {
+            Some(0) => 0,
+            Some(code) if code > 0 => 1,
+            _ => 2
+        },
+        Err(error) => {
+            eprintln!("{}", error);
+            2
+        }
+    }
+}
@@ -9,13 +9,31 @@ pub mod field_type;
 
 pub mod table;
 
-use std::process::Command;
+pub mod transaction;
+
+pub mod git;
+
+pub mod diff;
+
+pub mod merge;
+
+pub mod snapshot;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
 use db_error::DbError;
 use uuid::Uuid;
 
+use diff::TableDiff;
+use git::{Blame, Commit, CommitOptions};
+use merge::{Conflict, MergeResult};
+use snapshot::Snapshot;
 use table::Table;
+use transaction::Transaction;
 
-use crate::table_manager::{self, TableManager};
+use crate::table_manager::{self, Options};
+use crate::util::file;
 
 use self::{line::Line, field_type::Type};
 
@@ -26,17 +44,43 @@ use self::{line::Line, field_type::Type};
 */
 pub struct Db {
     path: String,
-    use_git: bool
+    use_git: bool,
+    commit_options: CommitOptions,
+    options: Options,
+    /**
+     * The messages of the commits deferred until commit_batch (None when there is no batch)
+     */
+    batch: Mutex<Option<Vec<String>>>
+}
+
+/**
+ * The merge of a table (see Db::merge_tables)
+ */
+struct TableMerge {
+    name: String,
+    in_base: bool,
+    in_ours: bool,
+    in_theirs: bool,
+    ours: Table,
+    result: MergeResult
 }
 
 enum Config {
-    UseGit
+    UseGit,
+    UseJournal,
+    GitAuthorName,
+    GitAuthorEmail,
+    GitCommitFlags
 }
 
 impl Config {
     pub fn value(&self) -> &str {
         match self {
             Config::UseGit => "use_git",
+            Config::UseJournal => "use_journal",
+            Config::GitAuthorName => "git_author_name",
+            Config::GitAuthorEmail => "git_author_email",
+            Config::GitCommitFlags => "git_commit_flags",
         }
     }
 }
@@ -52,7 +96,10 @@ impl Db {
         
         let mut db = Db { 
             path: String::from(path),
-            use_git: false
+            use_git: false,
+            commit_options: CommitOptions::default(),
+            options: Options::default(),
+            batch: Mutex::new(None)
         };
         db.init()?;
         
@@ -62,55 +109,30 @@ impl Db {
     fn init(&mut self) -> Result<(), DbError> {
         std::fs::create_dir_all(&self.path)?;
         
+        let recovered = transaction::recover(self)?;
         self.init_default_config()?;
         
-        Ok(())
-    }
-    
-    fn git_init(&self) -> Result<(), DbError> {
-        if self.use_git {
-            if !self.git_exists() {
-                match Command::new("git")
-                .arg("init")
-                .arg(&self.path)
-                .output() {
-                    Err(error) => return Err(DbError::Custom(error.to_string())),
-                    Ok(_) => ()
-                };
-            }
+        if recovered {
+            self.git_commit("Recover interrupted transaction")?;
         }
         
         Ok(())
     }
     
     fn git_exists(&self) -> bool {
-        std::path::Path::new(&self.path).join(".git").exists()
+        git::exists(&self.path)
     }
     
     fn git_commit(&self, msg: &str) -> Result<(), DbError> {
+        if let Some(messages) = self.lock_batch().as_mut() {
+            messages.push(String::from(msg));
+            return Ok(());
+        }
+        
         if self.use_git {
-            self.git_init()?;
-            match Command::new("git")
-            .arg("-C")
-            .arg(&self.path)
-            .arg("add")
-            .arg(".")
-            .output() {
-                Err(error) => return Err(DbError::Custom(error.to_string())),
-                Ok(_) => ()
-            };
-            
-            match Command::new("git")
-            .arg("-C")
-            .arg(&self.path)
-            .arg("commit")
-            .arg("-m")
-            .arg(msg)
-            .output() {
-                Err(error) => return Err(DbError::Custom(error.to_string())),
-                Ok(_) => ()
-            };
-            
+            git::init(&self.path)?;
+            git::ignore(&self.path)?;
+            git::commit(&self.path, msg, &self.commit_options)?;
         }
         
         Ok(())
@@ -121,12 +143,25 @@ impl Db {
         
         // Default config fields
         self.create_config_field(Config::UseGit.value(), Type::from_bool(false))?;
+        self.create_config_field(Config::UseJournal.value(), Type::from_bool(false))?;
+        self.create_config_field(Config::GitAuthorName.value(), Type::from_str(""))?;
+        self.create_config_field(Config::GitAuthorEmail.value(), Type::from_str(""))?;
+        self.create_config_field(Config::GitCommitFlags.value(), Type::from_str(""))?;
         
         
         if let Type::Boolean(b) = self.get_config(Config::UseGit.value())? {
             self.use_git = b;
         }
         
+        if let Type::Boolean(b) = self.get_config(Config::UseJournal.value())? {
+            self.options.journal = b;
+        }
+        
+        self.commit_options.author_name = self.get_config(Config::GitAuthorName.value())?.to_str()?;
+        self.commit_options.author_email = self.get_config(Config::GitAuthorEmail.value())?.to_str()?;
+        let flags = self.get_config(Config::GitCommitFlags.value())?.to_str()?;
+        self.commit_options.flags = flags.split_whitespace().map(String::from).collect();
+        
         Ok(())
     }
     
@@ -185,6 +220,22 @@ impl Db {
         Ok(())
     }
     
+    fn set_config(&self, config_name: &str, value: Type) -> Result<(), DbError> {
+        let mut table = self.get_config_table()?;
+        let line = match table.find_by_id(&self.get_config_id()?) {
+            Some(l) => l,
+            None => return Err(DbError::Custom(String::from("The config line was not found...")))
+        };
+        
+        let field = match line.get_mut(config_name) {
+            Some(f) => f,
+            None => return Err(DbError::Custom(String::from("The field [") + config_name + "] was not found..."))
+        };
+        field.set(value);
+        
+        self.write(&mut table)
+    }
+    
     fn get_config_id(&self) -> Result<Uuid, DbError> {
         match Uuid::parse_str("11111111-1111-1111-1111-111111111111") {
             Ok(id) => Ok(id),
@@ -238,12 +289,105 @@ impl Db {
         Ok(())
     }
     
+    /**
+    * The name of the author of the commits (empty to use the identity configured in git)
+    */
+    pub fn get_git_author_name(&self) -> &str {
+        &self.commit_options.author_name
+    }
+    
+    /**
+    * Sets the name of the author of the commits (empty to use the identity configured in git)
+    */
+    pub fn set_git_author_name(&mut self, name: &str) -> Result<(), DbError> {
+        self.set_config(Config::GitAuthorName.value(), Type::from_str(name))?;
+        self.commit_options.author_name = String::from(name);
+        
+        Ok(())
+    }
+    
+    /**
+    * The email of the author of the commits (empty to use the identity configured in git)
+    */
+    pub fn get_git_author_email(&self) -> &str {
+        &self.commit_options.author_email
+    }
+    
+    /**
+    * Sets the email of the author of the commits (empty to use the identity configured in git)
+    */
+    pub fn set_git_author_email(&mut self, email: &str) -> Result<(), DbError> {
+        self.set_config(Config::GitAuthorEmail.value(), Type::from_str(email))?;
+        self.commit_options.author_email = String::from(email);
+        
+        Ok(())
+    }
+    
+    /**
+    * The extra flags given to git commit
+    */
+    pub fn get_git_commit_flags(&self) -> &[String] {
+        &self.commit_options.flags
+    }
+    
+    /**
+    * Sets the extra flags given to git commit (e.g. ["-S"] to sign the commits)
+    * 
+    * A flag cannot contain spaces, use "--option=value" instead of "--option value"
+    */
+    pub fn set_git_commit_flags(&mut self, flags: &[&str]) -> Result<(), DbError> {
+        if let Some(flag) = flags.iter().find(|f| f.is_empty() || f.contains(char::is_whitespace)) {
+            return Err(DbError::Custom(String::from("Invalid git commit flag [") + flag + "]"));
+        }
+        
+        self.set_config(Config::GitCommitFlags.value(), Type::from_str(&flags.join(" ")))?;
+        self.commit_options.flags = flags.iter().map(|f| String::from(*f)).collect();
+        
+        Ok(())
+    }
+    
+    /**
+    * Allows to know if the changes are appended to the journal of the tables
+    */
+    pub fn get_use_journal(&self) -> bool {
+        self.options.journal
+    }
+    
+    /**
+    * Sets the use_journal parameter
+    * 
+    * When it is on, Db::write only appends the lines that changed to the journal of the table,
+    * instead of rewriting the whole table. The journal is folded back into the table
+    * once it gets bigger than the table.
+    */
+    pub fn set_use_journal(&mut self, use_journal: bool) -> Result<(), DbError> {
+        self.set_config(Config::UseJournal.value(), Type::from_bool(use_journal))?;
+        self.options.journal = use_journal;
+        
+        Ok(())
+    }
+    
+    /**
+    * How long the database waits for a table used by another process
+    */
+    pub fn get_lock_timeout(&self) -> Duration {
+        self.options.lock_timeout
+    }
+    
+    /**
+    * Sets how long the database waits for a table used by another process
+    * before failing with DbError::Locked
+    */
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.options.lock_timeout = timeout;
+    }
+    
     /**
     * This is where we can query the database. It reads the information
     * on disk and returns an object in memory with the corresponding data
     */
     pub fn table(&self, tbl: &str) -> Result<Table, DbError> {
-        let manager = table_manager::get_table_manager(&self.path, tbl)?;
+        let manager = table_manager::get_table_manager(&self.path, tbl, &self.options)?;
         
         match manager.read() {
             Ok(t) => Ok(t),
@@ -252,116 +396,794 @@ impl Db {
     }
     
     /**
-    * Lists the tables in the database
-    * It will ignore system table. All table starting with a "." are considered as system table.
+    * The commits that modified the table, the most recent first
+    * 
+    * It only works when the database uses git (see set_use_git)
     */
-    pub fn tables(&self) -> Result<Vec<String>, DbError> {
-        let paths = std::fs::read_dir(&self.path)?;
+    pub fn history(&self, tbl: &str) -> Result<Vec<Commit>, DbError> {
+        self.check_git()?;
         
-        let mut tables: Vec<String> = Vec::new();
-        for path in paths {
-            if let Some(name) = path?.file_name().to_str() {
-                if !name.starts_with(".") {
-                    tables.push(String::from(name));
+        let journal = file::journal_path(tbl);
+        git::log(&self.path, &[tbl, &journal])
+    }
+    
+    /**
+    * Finds the commits that added the line, and that changed each of its fields last
+    * 
+    * Every revision of the table is read, from the oldest to the most recent, and
+    * compared with its parents: a field that has the value of one of the parents
+    * keeps the commit of this parent (e.g. the changes brought by a merge_branch).
+    * It only works when the database uses git (see set_use_git)
+    */
+    pub fn blame(&self, tbl: &str, id: &Uuid) -> Result<Blame, DbError> {
+        let commits: HashMap<String, Commit> = self.history(tbl)?.into_iter().map(|c| (c.id.clone(), c)).collect();
+        
+        // The line after each commit, with the commit that added it and the commits of its fields
+        type LineBlame = (Line, String, Vec<(String, String)>);
+        let mut blames: HashMap<String, Option<LineBlame>> = HashMap::new();
+        let mut last = None;
+        
+        let journal = file::journal_path(tbl);
+        for (commit, parents) in git::parents(&self.path, &[tbl, &journal])? {
+            let line = match self.table_at_commit(tbl, &commit)? {
+                Some(table) => table.get(id).cloned(),
+                None => None
+            };
+            
+            let blame = line.map(|line| {
+                let before: Vec<_> = parents.iter().filter_map(|p| blames.get(p).and_then(Option::as_ref)).collect();
+                
+                // Added (or added again) when no parent has the line
+                let created = before.first().map_or(commit.clone(), |(_, created, _)| created.clone());
+                
+                let mut fields = Vec::new();
+                for field in line.get_fields() {
+                    let name = field.get_name();
+                    let from = before.iter().find_map(|(old, _, old_fields)| match old.get(name) {
+                        Some(old) if old.get() == field.get() => old_fields.iter().find(|(n, _)| n == name).map(|(_, c)| c.clone()),
+                        _ => None
+                    });
+                    fields.push((String::from(name), from.unwrap_or_else(|| commit.clone())));
                 }
-            }
+                
+                (line, created, fields)
+            });
+            
+            blames.insert(commit.clone(), blame);
+            last = Some(commit);
         }
         
-        Ok(tables)
+        match last.and_then(|commit| blames.remove(&commit)).flatten() {
+            Some((_, created, fields)) => {
+                let commit = |id: &String| commits.get(id).cloned().ok_or_else(|| DbError::Custom(String::from("Unknown commit [") + id + "]"));
+                
+                Ok(Blame {
+                    created: commit(&created)?,
+                    fields: fields.iter().map(|(name, id)| Ok((name.clone(), commit(id)?))).collect::<Result<_, DbError>>()?
+                })
+            },
+            None => Err(DbError::Custom(String::from("The line [") + &id.to_string() + "] does not exist in the table [" + tbl + "]"))
+        }
     }
     
     /**
-    * Drops the table from the database
+    * Reads the table as it was at the given revision (a commit hash, a branch, HEAD~1, etc.)
+    * 
+    * It only works when the database uses git (see set_use_git)
     */
-    pub fn drop(&self, tbl: &str) -> Result<(), DbError> {
-        let manager = table_manager::get_table_manager(&self.path, tbl)?;
-        manager.drop()?;
+    pub fn table_at(&self, tbl: &str, rev: &str) -> Result<Table, DbError> {
+        self.check_git()?;
         
-        let msg = String::from("Drop table ") + "[" + tbl + "]";
-        self.git_commit(&msg)?;
+        let commit = git::resolve(&self.path, rev)?;
+        match self.table_at_commit(tbl, &commit)? {
+            Some(table) => Ok(table),
+            None => Err(DbError::Custom(String::from("The table [") + tbl + "] does not exist at [" + rev + "]"))
+        }
+    }
+    
+    fn table_at_commit(&self, tbl: &str, commit: &str) -> Result<Option<Table>, DbError> {
+        Db::read_table_at(&self.path, tbl, commit)
+    }
+    
+    /**
+    * Reads the table (with its journal) at the commit of the repository (None if it did not exist)
+    */
+    fn read_table_at(path: &str, tbl: &str, commit: &str) -> Result<Option<Table>, DbError> {
+        let content = match git::show(path, commit, tbl)? {
+            Some(c) => c,
+            None => return Ok(None)
+        };
+        let journal = git::show(path, commit, &file::journal_path(tbl))?;
         
-        Ok(())
+        // Read the files with the table manager, from a temporary folder
+        let dir = std::env::temp_dir().join(String::from("rental_rod_") + &Uuid::new_v4().to_string());
+        let result = Db::read_files(&dir, tbl, &content, journal.as_deref());
+        std::fs::remove_dir_all(&dir)?;
+        
+        Ok(Some(result?))
     }
     
     /**
-    * This writes (or commits) all the changes from a Table to the database
+    * Returns the differences in the table between two revisions
+    * 
+    * A table that did not exist at a revision is considered empty.
+    * It only works when the database uses git (see set_use_git)
     */
-    pub fn write(&self, table: &mut Table) -> Result<(), DbError> {
-        let mut manager = table_manager::get_table_manager(&self.path, table.get_name())?;
+    pub fn diff(&self, tbl: &str, from: &str, to: &str) -> Result<TableDiff, DbError> {
+        self.check_git()?;
         
-        let msg: String;
-        if manager.write(table)? {
-            msg = String::from("Create table ") + "[" + table.get_name() + "]";
-        }
-        else {
-            msg = String::from("Update table ") + "[" + table.get_name() + "]";
+        let mut tables = Vec::new();
+        for rev in [from, to] {
+            let commit = git::resolve(&self.path, rev)?;
+            let table = match self.table_at_commit(tbl, &commit)? {
+                Some(table) => table,
+                None => Table::new(tbl, vec![])?
+            };
+            tables.push(table);
         }
         
-        self.git_commit(&msg)?;
-        Ok(())
+        Ok(diff::diff(&tables[0], &tables[1]))
     }
     
-}
-
-#[test]
-fn test_dont_use_git() {
-    let p = "/tmp/test_dont_use_git";
-    let db = _init_db(p, true);
-    assert_eq!(db.git_exists(), false);
-    assert_eq!(db.use_git, false);
+    /**
+    * Registers the rental_rod_merge binary as the git merge driver of the tables
+    * 
+    * `command` is the path to the binary (or its name, if it is in the PATH). When two
+    * branches change the same table, the lines are merged by id and field by field
+    * (see merge). The .gitattributes file is committed, but the driver itself is only
+    * registered in the local git config: it must be set on every clone.
+    * 
+    * A table and its journal are merged as one table, the entries of the journals are kept.
+    */
+    pub fn set_merge_driver(&self, command: &str) -> Result<(), DbError> {
+        self.check_git()?;
+        git::set_merge_driver(&self.path, command)?;
+        
+        self.git_commit("Use the table merge driver")
+    }
     
-    let mut table = db.table("test").unwrap();
-    table.insert(Line::new());
-    db.write(&mut table).unwrap();
+    /**
+    * Creates a branch of the database on the current commit
+    */
+    pub fn create_branch(&self, name: &str) -> Result<(), DbError> {
+        self.check_git()?;
+        
+        git::create_branch(&self.path, name)
+    }
     
-    let log = _git_log(&db);
-    assert_eq!(db.git_exists(), false);
-    assert_eq!(log.len(), 0);
+    /**
+    * Switches the database to the branch
+    * 
+    * The tables read before are not up to date anymore, writing them fails with DbError::Conflict
+    */
+    pub fn checkout_branch(&mut self, name: &str) -> Result<(), DbError> {
+        self.check_git()?;
+        self.check_no_batch()?;
+        git::checkout(&self.path, name)?;
+        
+        // The config can be different on the branch
+        self.init_default_config()
+    }
     
-    let db = _init_db(p, false);
-    assert_eq!(db.use_git, false);
-    let mut table = db.table("test").unwrap();
+    /**
+    * The name of the current branch
+    */
+    pub fn get_branch(&self) -> Result<String, DbError> {
+        self.check_git()?;
+        
+        git::current_branch(&self.path)
+    }
     
-    let log = _git_log(&db);
-    assert_eq!(db.git_exists(), false);
-    assert_eq!(log.len(), 0);
+    /**
+    * Merges the branch into the current branch
+    * 
+    * The tables are merged line by line, and field by field (see merge). If both branches
+    * changed the same field of a line, nothing is merged and it fails with DbError::MergeConflict
+    * (see merge_conflicts). The system tables (like .config) are not merged.
+    * 
+    * It fails with DbError::Uncommitted if some files changed since the last commit.
+    */
+    pub fn merge_branch(&mut self, name: &str) -> Result<(), DbError> {
+        self.check_git()?;
+        self.check_no_batch()?;
+        
+        let commit = git::resolve(&self.path, name)?;
+        self.merge_commit(&commit, &(String::from("Merge branch [") + name + "]"))
+    }
     
-    table.insert(Line::new());
+    /**
+    * The lines that would be in conflict when merging the branch, with their table
+    */
+    pub fn merge_conflicts(&self, name: &str) -> Result<Vec<(String, Conflict)>, DbError> {
+        self.check_git()?;
+        
+        let commit = git::resolve(&self.path, name)?;
+        let base = git::merge_base(&self.path, "HEAD", &commit)?;
+        
+        let mut conflicts = Vec::new();
+        for merge in self.merge_tables(base.as_deref(), &commit)? {
+            for conflict in merge.result.conflicts {
+                conflicts.push((merge.name.clone(), conflict));
+            }
+        }
+        
+        Ok(conflicts)
+    }
     
-    let log = _git_log(&db);
-    assert_eq!(db.git_exists(), false);
-    assert_eq!(log.len(), 0);
-    db.write(&mut table).unwrap();
+    /**
+    * Synchronises the current branch with the same branch of the remote (a name or an url)
+    * 
+    * The changes of the remote are merged like merge_branch does, and the result is pushed.
+    * It fails with DbError::MergeConflict if both sides changed the same fields (nothing is
+    * merged or pushed), with DbError::Diverged if they have no common history, and with
+    * DbError::Uncommitted if some files changed since the last commit.
+    */
+    pub fn sync(&mut self, remote: &str) -> Result<(), DbError> {
+        self.check_git()?;
+        self.check_no_batch()?;
+        let branch = git::current_branch(&self.path)?;
+        
+        if let Some(commit) = git::fetch(&self.path, remote, &branch)? {
+            if git::merge_base(&self.path, "HEAD", &commit)?.is_none() {
+                return Err(DbError::Diverged(String::from(remote)));
+            }
+            
+            let msg = String::from("Merge [") + remote + "] into [" + &branch + "]";
+            self.merge_commit(&commit, &msg)?;
+        }
+        
+        git::push(&self.path, remote, &branch)
+    }
     
-    let mut table = db.table("tbl").unwrap();
-    table.insert(Line::new());
-    db.write(&mut table).unwrap();
+    fn merge_commit(&mut self, commit: &str, msg: &str) -> Result<(), DbError> {
+        let head = git::resolve(&self.path, "HEAD")?;
+        let base = git::merge_base(&self.path, &head, commit)?;
+        if base.as_deref() == Some(commit) {
+            // Already up to date
+            return Ok(());
+        }
+        if base.as_deref() == Some(head.as_str()) {
+            git::fast_forward(&self.path, commit)?;
+            return self.init_default_config();
+        }
+        
+        let merges = self.merge_tables(base.as_deref(), commit)?;
+        let conflicts: Vec<String> = merges.iter()
+            .filter(|m| !m.result.is_clean())
+            .map(|m| m.name.clone())
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(DbError::MergeConflict(conflicts));
+        }
+        
+        // Nobody can write the tables until the merge is committed
+        let merge_names: Vec<String> = merges.iter().map(|m| m.name.clone()).collect();
+        let mut managers = Vec::new();
+        let mut locks = Vec::new();
+        for merge in &merges {
+            let manager = table_manager::get_table_manager(&self.path, &merge.name, &self.options)?;
+            locks.push(manager.lock()?);
+            managers.push(manager);
+        }
+        
+        // The tables are merged as they are in HEAD, the other changes would be overwritten
+        let changed = git::changed_files(&self.path)?;
+        if !changed.is_empty() {
+            return Err(DbError::Uncommitted(changed));
+        }
+        if git::resolve(&self.path, "HEAD")? != head {
+            return Err(DbError::Custom(String::from("A commit was made during the merge, nothing was merged")));
+        }
+        
+        // Every table is staged before the merge starts, nothing is changed if one of them cannot be written
+        let staged = Db::stage_merges(merges, &managers).and_then(|staged| {
+            git::start_merge(&self.path, commit)?;
+            Ok(staged)
+        });
+        let staged = match staged {
+            Ok(staged) => staged,
+            Err(error) => {
+                for manager in &managers {
+                    let _ = manager.rollback();
+                }
+                return Err(error);
+            }
+        };
+        
+        let mut written = Vec::new();
+        let mut result = Ok(());
+        for (i, table) in staged {
+            written.push(merge_names[i].clone());
+            written.push(file::journal_path(&merge_names[i]));
+            result = match table {
+                Some(mut table) => managers[i].commit(&mut table).map(|_| ()),
+                None => managers[i].remove()
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() {
+            result = self.git_commit(msg);
+        }
+        
+        if result.is_err() {
+            // Nothing of the merge is kept, the next commit must not complete it
+            for manager in &managers {
+                let _ = manager.rollback();
+            }
+            git::abort_merge(&self.path)?;
+            git::restore_files(&self.path, &head, &written)?;
+        }
+        drop(locks);
+        
+        result
+    }
     
-    let log = _git_log(&db);
-    assert_eq!(db.git_exists(), false);
-    assert_eq!(log.len(), 0);
-}
-
-#[test]
-fn test_git_init_when_db_is_not_empty() {
-    let p = "/tmp/test_git_db_not_empty";
-    let db = _init_db(p, true);
-    assert_eq!(db.git_exists(), false);
+    /**
+    * Stage the merged tables, with the index of their manager
+    * 
+    * The table is None when it must be removed. The tables that did not change are skipped.
+    */
+    fn stage_merges(merges: Vec<TableMerge>, managers: &[Box<dyn table_manager::TableManager>]) -> Result<Vec<(usize, Option<Table>)>, DbError> {
+        let mut staged = Vec::new();
+        for (i, merge) in merges.into_iter().enumerate() {
+            if merge.result.lines.is_empty() && merge.in_base && !(merge.in_ours && merge.in_theirs) {
+                // One side dropped the table, the other one did not add any line
+                if merge.in_ours {
+                    staged.push((i, None));
+                }
+            }
+            else if !merge.in_ours || !merge.result.lines.iter().eq(merge.ours.get_lines()) {
+                // A table created by the other side is created, even if it is empty
+                let table = Table::new(&merge.name, merge.result.lines)?;
+                managers[i].stage(&table, true)?;
+                staged.push((i, Some(table)));
+            }
+        }
+        
+        Ok(staged)
+    }
     
-    let mut table = db.table("without").unwrap();
-    table.insert(Line::new());
-    db.write(&mut table).unwrap();
+    /**
+    * Merge every table of the current commit with the tables of the other commit
+    */
+    fn merge_tables(&self, base: Option<&str>, commit: &str) -> Result<Vec<TableMerge>, DbError> {
+        let mut names = self.tables()?;
+        for name in git::files(&self.path, commit)? {
+            if !name.starts_with(".") && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names.sort();
+        
+        let mut merges = Vec::new();
+        for name in names {
+            let at = |commit: Option<&str>| -> Result<Option<Table>, DbError> {
+                match commit {
+                    Some(c) => self.table_at_commit(&name, c),
+                    None => Ok(None)
+                }
+            };
+            let empty = || Table::new(&name, vec![]);
+            
+            let base = at(base)?;
+            let ours = at(Some("HEAD"))?;
+            let theirs = at(Some(commit))?;
+            let (in_base, in_ours, in_theirs) = (base.is_some(), ours.is_some(), theirs.is_some());
+            
+            let ours = match ours { Some(t) => t, None => empty()? };
+            let result = merge::merge(&base.map_or_else(empty, Ok)?, &ours, &theirs.map_or_else(empty, Ok)?);
+            merges.push(TableMerge { name, in_base, in_ours, in_theirs, ours, result });
+        }
+        
+        Ok(merges)
+    }
     
-    assert_eq!(db.git_exists(), false);
-    assert_eq!(db.use_git, false);
-    assert_eq!(_git_log(&db).len(), 0);
+    /**
+    * Rewrites the table as it was at the given revision, in a new commit
+    * 
+    * The history is not rewritten. If the table did not exist at that revision, it is dropped.
+    */
+    pub fn restore_table(&self, tbl: &str, rev: &str) -> Result<(), DbError> {
+        self.check_git()?;
+        
+        let commit = git::resolve(&self.path, rev)?;
+        self.restore(tbl, &commit)?;
+        
+        let msg = String::from("Restore table ") + "[" + tbl + "] to " + &commit[..7];
+        self.git_commit(&msg)
+    }
+    
+    /**
+    * Rewrites all the tables as they were at the given revision, in a new commit
+    * 
+    * The system tables (like .config) are not restored. The tables that did not exist
+    * at that revision are dropped. The tables are replaced like in a transaction:
+    * if one of them cannot be written, none of them is.
+    */
+    pub fn restore_all(&self, rev: &str) -> Result<(), DbError> {
+        self.check_git()?;
+        
+        let commit = git::resolve(&self.path, rev)?;
+        let mut tables: Vec<String> = git::files(&self.path, &commit)?
+            .into_iter()
+            .filter(|name| !name.starts_with("."))
+            .collect();
+        for tbl in self.tables()? {
+            if !tables.contains(&tbl) {
+                tables.push(tbl);
+            }
+        }
+        tables.sort();
+        
+        // Every table is read before anything is written
+        let mut restored = Vec::new();
+        let mut dropped = Vec::new();
+        let mut msg = String::from("Restore all tables to ") + &commit[..7] + "\n";
+        for tbl in &tables {
+            match self.table_at_commit(tbl, &commit)? {
+                Some(table) => restored.push(table),
+                None => dropped.push(tbl)
+            }
+            msg.push_str(&(String::from("\nRestore table [") + tbl + "]"));
+        }
+        
+        let mut tx = self.transaction();
+        for table in restored.iter_mut() {
+            tx.force_write(table)?;
+        }
+        for tbl in dropped {
+            tx.drop_table(tbl)?;
+        }
+        
+        tx.commit_with_message(&msg)
+    }
+    
+    fn restore(&self, tbl: &str, commit: &str) -> Result<(), DbError> {
+        let mut manager = table_manager::get_table_manager(&self.path, tbl, &self.options)?;
+        match self.table_at_commit(tbl, commit)? {
+            Some(mut table) => {
+                manager.write(&mut table, true)?;
+            },
+            None => manager.as_ref().drop()?
+        }
+        
+        Ok(())
+    }
+    
+    fn read_files(dir: &std::path::Path, tbl: &str, content: &[u8], journal: Option<&[u8]>) -> Result<Table, DbError> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(tbl);
+        std::fs::write(&path, content)?;
+        if let Some(journal) = journal {
+            std::fs::write(file::journal_path(&path.to_string_lossy()), journal)?;
+        }
+        
+        let manager = table_manager::get_table_manager(&dir.to_string_lossy(), tbl, &Options::default())?;
+        manager.read()
+    }
+    
+    fn check_git(&self) -> Result<(), DbError> {
+        if !self.use_git || !self.git_exists() {
+            return Err(DbError::Custom(String::from("The database does not use git")));
+        }
+        
+        Ok(())
+    }
+    
+    /**
+    * Lists the tables in the database
+    * It will ignore system table. All table starting with a "." are considered as system table.
+    */
+    pub fn tables(&self) -> Result<Vec<String>, DbError> {
+        let paths = std::fs::read_dir(&self.path)?;
+        
+        let mut tables: Vec<String> = Vec::new();
+        for path in paths {
+            if let Some(name) = path?.file_name().to_str() {
+                if !name.starts_with(".") {
+                    tables.push(String::from(name));
+                }
+            }
+        }
+        
+        Ok(tables)
+    }
+    
+    /**
+    * Rewrites the table file in its canonical form
+    * 
+    * The journal, the deleted lines and the replaced lines are folded into the table file.
+    * Returns the number of bytes reclaimed.
+    */
+    pub fn compact(&self, tbl: &str) -> Result<u64, DbError> {
+        let manager = table_manager::get_table_manager(&self.path, tbl, &self.options)?;
+        let reclaimed = manager.compact()?;
+        
+        let msg = String::from("Compact table ") + "[" + tbl + "]";
+        self.git_commit(&msg)?;
+        
+        Ok(reclaimed)
+    }
+    
+    /**
+    * Compacts all the tables (see compact)
+    * 
+    * Returns the number of bytes reclaimed for each table
+    */
+    pub fn compact_all(&self) -> Result<Vec<(String, u64)>, DbError> {
+        let mut reclaimed = Vec::new();
+        for tbl in self.tables()? {
+            let bytes = self.compact(&tbl)?;
+            reclaimed.push((tbl, bytes));
+        }
+        
+        Ok(reclaimed)
+    }
+    
+    /**
+    * Converts the table file to the format of `version` (1 or 2, the new tables use the version 1)
+    * 
+    * The table is read with its current format and written with the new one. The new file is
+    * read back and compared to the table before it replaces the old one. A table cannot be
+    * downgraded. Returns false if the table already used this version.
+    * 
+    * The tables read before the upgrade must be read again, writing them fails with DbError::Conflict.
+    */
+    pub fn upgrade_table(&self, tbl: &str, version: u32) -> Result<bool, DbError> {
+        let upgraded = table_manager::upgrade(&self.path, tbl, &self.options, version)?;
+        
+        if upgraded {
+            let msg = String::from("Upgrade table [") + tbl + "] to v" + &version.to_string();
+            self.git_commit(&msg)?;
+        }
+        
+        Ok(upgraded)
+    }
+    
+    /**
+    * Upgrades all the tables to the most recent version of the format (see upgrade_table)
+    * 
+    * Returns the names of the tables that were upgraded
+    */
+    pub fn upgrade_all(&self) -> Result<Vec<String>, DbError> {
+        let mut upgraded = Vec::new();
+        for tbl in self.tables()? {
+            if self.upgrade_table(&tbl, table_manager::LATEST_VERSION)? {
+                upgraded.push(tbl);
+            }
+        }
+        
+        Ok(upgraded)
+    }
+    
+    /**
+    * Drops the table from the database
+    */
+    pub fn drop(&self, tbl: &str) -> Result<(), DbError> {
+        let manager = table_manager::get_table_manager(&self.path, tbl, &self.options)?;
+        manager.as_ref().drop()?;
+        
+        let msg = String::from("Drop table ") + "[" + tbl + "]";
+        self.git_commit(&msg)?;
+        
+        Ok(())
+    }
+    
+    /**
+    * This writes (or commits) all the changes from a Table to the database
+    * 
+    * It fails with DbError::Conflict if the table was modified on disk since it was read.
+    * In that case, read the table again and redo the changes (or use force_write).
+    * 
+    * The git commit message describes the changes, e.g. "Update table [orders]: +2 lines, -1 line"
+    */
+    pub fn write(&self, table: &mut Table) -> Result<(), DbError> {
+        self.write_table(table, false, None)
+    }
+    
+    /**
+    * Like write, but overwrites the table even if it was modified on disk since it was read
+    * 
+    * The changes made by the others are lost
+    */
+    pub fn force_write(&self, table: &mut Table) -> Result<(), DbError> {
+        self.write_table(table, true, None)
+    }
+    
+    /**
+    * Like write, but uses the given git commit message as is
+    * 
+    * The message can end with trailers (e.g. "Signed-off-by: ..."). To keep the
+    * description of the changes, start the message with Table::describe_changes.
+    */
+    pub fn write_with_message(&self, table: &mut Table, msg: &str) -> Result<(), DbError> {
+        self.write_table(table, false, Some(msg))
+    }
+    
+    fn write_table(&self, table: &mut Table, force: bool, msg: Option<&str>) -> Result<(), DbError> {
+        let mut manager = table_manager::get_table_manager(&self.path, table.get_name(), &self.options)?;
+        
+        // The changes are reset by the write
+        let changes = table.describe_changes();
+        let created = manager.write(table, force)?;
+        
+        match msg {
+            Some(msg) => self.git_commit(msg)?,
+            None => self.git_commit(&Db::write_message(table, created, &changes))?
+        }
+        Ok(())
+    }
+    
+    fn write_message(table: &Table, created: bool, changes: &str) -> String {
+        let mut msg = if created {
+            String::from("Create table ") + "[" + table.get_name() + "]"
+        }
+        else {
+            String::from("Update table ") + "[" + table.get_name() + "]"
+        };
+        
+        if !changes.is_empty() {
+            msg.push_str(": ");
+            msg.push_str(changes);
+        }
+        
+        msg
+    }
+    
+    /**
+    * Marks the current state of the whole database with a name (e.g. "2026-01")
+    * 
+    * When the database uses git, the snapshot is a tag ("snapshot/<name>") on the last commit:
+    * it fails with DbError::Uncommitted if some files changed since. Otherwise, the tables
+    * are copied to a folder. The name can only contain letters, digits, '-', '_' and '.'
+    */
+    pub fn snapshot(&self, name: &str) -> Result<(), DbError> {
+        self.check_no_batch()?;
+        snapshot::create(self, name)
+    }
+    
+    /**
+    * Lists the names of the snapshots
+    */
+    pub fn snapshots(&self) -> Result<Vec<String>, DbError> {
+        snapshot::list(self)
+    }
+    
+    /**
+    * Opens a read-only view of the database, as it was when the snapshot was taken
+    */
+    pub fn open_snapshot(&self, name: &str) -> Result<Snapshot<'_>, DbError> {
+        Snapshot::open(self, name)
+    }
+    
+    /**
+    * Defers the git commits until commit_batch, to group a lot of writes in a single commit
+    * 
+    * The changes are still written to the tables right away.
+    */
+    pub fn begin_batch(&self) -> Result<(), DbError> {
+        let mut batch = self.lock_batch();
+        if batch.is_some() {
+            return Err(DbError::Custom(String::from("A batch is already in progress")));
+        }
+        *batch = Some(Vec::new());
+        
+        Ok(())
+    }
+    
+    /**
+    * Commits all the changes made since begin_batch in a single commit
+    * 
+    * The message of each deferred commit is added to the body of the commit
+    */
+    pub fn commit_batch(&self, msg: &str) -> Result<(), DbError> {
+        let messages = match self.lock_batch().take() {
+            Some(messages) => messages,
+            None => return Err(DbError::Custom(String::from("There is no batch in progress")))
+        };
+        
+        let mut msg = String::from(msg);
+        if !messages.is_empty() {
+            msg.push('\n');
+            for message in messages {
+                msg.push('\n');
+                msg.push_str(&message);
+            }
+        }
+        
+        self.git_commit(&msg)
+    }
+    
+    /**
+    * Allows to know if the git commits are deferred (see begin_batch)
+    */
+    pub fn in_batch(&self) -> bool {
+        self.lock_batch().is_some()
+    }
+    
+    fn lock_batch(&self) -> std::sync::MutexGuard<'_, Option<Vec<String>>> {
+        // The messages are still usable if another thread panicked
+        self.batch.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    fn check_no_batch(&self) -> Result<(), DbError> {
+        if self.in_batch() {
+            return Err(DbError::Custom(String::from("Not possible while a batch is in progress, see commit_batch")));
+        }
+        
+        Ok(())
+    }
+    
+    /**
+    * Start a transaction, to write multiple tables at once (all or nothing)
+    */
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+    
+}
+
+#[test]
+fn test_dont_use_git() {
+    let p = "/tmp/test_dont_use_git";
+    let db = _init_db(p, true);
+    assert_eq!(db.git_exists(), false);
+    assert_eq!(db.use_git, false);
+    
+    let mut table = db.table("test").unwrap();
+    table.insert(Line::new());
+    db.write(&mut table).unwrap();
+    
+    let log = _git_log(&db);
+    assert_eq!(db.git_exists(), false);
+    assert_eq!(log.len(), 0);
+    
+    let db = _init_db(p, false);
+    assert_eq!(db.use_git, false);
+    let mut table = db.table("test").unwrap();
+    
+    let log = _git_log(&db);
+    assert_eq!(db.git_exists(), false);
+    assert_eq!(log.len(), 0);
+    
+    table.insert(Line::new());
+    
+    let log = _git_log(&db);
+    assert_eq!(db.git_exists(), false);
+    assert_eq!(log.len(), 0);
+    db.write(&mut table).unwrap();
+    
+    let mut table = db.table("tbl").unwrap();
+    table.insert(Line::new());
+    db.write(&mut table).unwrap();
+    
+    let log = _git_log(&db);
+    assert_eq!(db.git_exists(), false);
+    assert_eq!(log.len(), 0);
+}
+
+#[test]
+fn test_git_init_when_db_is_not_empty() {
+    let p = "/tmp/test_git_db_not_empty";
+    let db = _init_db(p, true);
+    assert_eq!(db.git_exists(), false);
+    
+    let mut table = db.table("without").unwrap();
+    table.insert(Line::new());
+    db.write(&mut table).unwrap();
+    
+    assert_eq!(db.git_exists(), false);
+    assert_eq!(db.use_git, false);
+    assert_eq!(_git_log(&db).len(), 0);
     
     let mut db = _init_db(p, false);
     db.set_use_git(true, None).unwrap();
     assert_eq!(db.git_exists(), true);
     let log = _git_log(&db);
     assert_eq!(log.len(), 2);
-    assert_eq!(log[0], "Update table [.config]");
+    assert_eq!(log[0], "Update table [.config]: 1 field changed on 1111…");
     assert_eq!(log[1], "Commit all changes since last git activation");
 }
 
@@ -383,8 +1205,8 @@ fn test_use_git() {
     let log = _git_log(&db);
     assert_eq!(log.len(), 3);
     
-    assert_eq!(log[0], "Create table [test]");
-    assert_eq!(log[1], "Update table [.config]");
+    assert_eq!(log[0], "Create table [test]: +1 line");
+    assert_eq!(log[1], "Update table [.config]: 1 field changed on 1111…");
     assert_eq!(log[2], "Test batch commit message");
     
     let db = _init_db(p, false);
@@ -407,10 +1229,10 @@ fn test_use_git() {
     
     let log = _git_log(&db);
     assert_eq!(log.len(), 5);
-    assert_eq!(log[0], "Create table [tbl]");
-    assert_eq!(log[1], "Update table [test]");
-    assert_eq!(log[2], "Create table [test]");
-    assert_eq!(log[3], "Update table [.config]");
+    assert_eq!(log[0], "Create table [tbl]: +1 line");
+    assert_eq!(log[1], "Update table [test]: +1 line");
+    assert_eq!(log[2], "Create table [test]: +1 line");
+    assert_eq!(log[3], "Update table [.config]: 1 field changed on 1111…");
     assert_eq!(log[4], "Test batch commit message");
     
 }
@@ -537,37 +1359,1134 @@ fn test_write() {
     assert_eq!(db.table("write").unwrap().get_lines().len(), 1);
 }
 
-
-
-fn _init_db(p: &str, fresh: bool) -> Db {
-    if std::path::Path::new(p).exists() && fresh {
-        std::fs::remove_dir_all(p).unwrap();
-    }
+#[test]
+fn test_write_messages() {
+    let p = "/tmp/test_write_messages";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
     
-    Db::new(p).unwrap()
-}
-
-fn _new_test_line() -> Line {
-    let mut line = Line::new();
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    table.insert(_new_test_line());
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    assert_eq!(_git_log(&db)[0], "Create table [orders]: +3 lines");
+    
+    let id = *table.get_lines()[0].get_id();
+    table.delete(&id);
+    let line = table.get_lines()[0].clone();
+    let changed = table.find_by_id(line.get_id()).unwrap();
+    changed.get_mut("col1").unwrap().set(Type::from_int(1));
+    changed.remove("col2");
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
     
-    line.add("col1", Type::from_str("123")).unwrap();
-    line.add("col2", Type::from_str("456")).unwrap();
-    line.add("col3", Type::from_str("789")).unwrap();
+    let short_id = line.get_id().to_string()[..4].to_owned();
+    assert_eq!(_git_log(&db)[0], String::from("Update table [orders]: +1 line, -1 line, 2 fields changed on ") + &short_id + "…");
     
-    line
+    // Nothing changed, nothing is committed
+    let count = _git_log(&db).len();
+    db.write(&mut table).unwrap();
+    assert_eq!(_git_log(&db).len(), count);
+    
+    // Custom message with a trailer
+    table.insert(_new_test_line());
+    let msg = table.describe_changes() + "\n\nSigned-off-by: Mike <mike@example.com>";
+    db.write_with_message(&mut table, &msg).unwrap();
+    assert_eq!(_git_log(&db)[0], "+1 line");
+    
+    let output = std::process::Command::new("git")
+    .arg("-C")
+    .arg(p)
+    .arg("log")
+    .arg("-1")
+    .arg("--format=%(trailers:key=Signed-off-by,valueonly)")
+    .output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "Mike <mike@example.com>");
 }
 
-fn _git_log(db: &Db) -> Vec<String> {
-    let mut formatted_lines = vec![];
+#[test]
+fn test_history() {
+    let p = "/tmp/test_history";
+    let mut db = _init_db(p, true);
+    assert!(db.history("orders").is_err());
     
-    if db.git_exists() {
-        let output = std::process::Command::new("git")
-        .arg("-C")
-        .arg(&db.path)
-        .arg("log")
-        .arg("--oneline")
-        .output().unwrap();
-        
+    db.set_use_git(true, None).unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    let mut other = db.table("other").unwrap();
+    other.insert(_new_test_line());
+    db.write(&mut other).unwrap();
+    
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    let history = db.history("orders").unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].message, "Update table [orders]: +1 line");
+    assert_eq!(history[1].message, "Create table [orders]: +1 line");
+    assert!(!history[0].author.is_empty());
+    assert!(history[0].timestamp <= chrono::Utc::now());
+    assert_eq!(history[0].id.len(), 40);
+    
+    // The changes appended to the journal are part of the history
+    db.set_use_journal(true).unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    assert_eq!(db.history("orders").unwrap().len(), 3);
+    
+    assert!(db.history("unknown").unwrap().is_empty());
+}
+
+#[test]
+fn test_table_at() {
+    let p = "/tmp/test_table_at";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    db.set_use_journal(true).unwrap();
+    let id = *table.get_lines()[0].get_id();
+    table.delete(&id);
+    table.insert(_new_test_line());
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    let history = db.history("orders").unwrap();
+    assert_eq!(db.table_at("orders", &history[2].id).unwrap().get_lines().len(), 1);
+    assert_eq!(db.table_at("orders", &history[1].id).unwrap().get_lines().len(), 2);
+    assert_eq!(db.table_at("orders", "HEAD").unwrap().get_lines().len(), 3);
+    assert!(db.table_at("orders", "HEAD").unwrap().get(&id).is_none());
+    assert!(db.table_at("orders", "HEAD~2").unwrap().get(&id).is_some());
+    
+    assert!(db.table_at("orders", "unknown").is_err());
+    assert!(db.table_at("unknown", "HEAD").is_err());
+}
+
+#[test]
+fn test_restore_table() {
+    let p = "/tmp/test_restore_table";
+    let mut db = _init_db(p, true);
+    assert!(db.restore_table("orders", "HEAD").is_err());
+    db.set_use_git(true, None).unwrap();
+    
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    let good = db.history("orders").unwrap()[0].id.clone();
+    
+    // Someone corrupts the data
+    let ids: Vec<Uuid> = table.get_lines().iter().map(|l| *l.get_id()).collect();
+    table.delete(&ids[0]);
+    table.find_by_id(&ids[1]).unwrap().get_mut("col1").unwrap().set(Type::from_str("oops"));
+    db.write(&mut table).unwrap();
+    let count = _git_log(&db).len();
+    
+    db.restore_table("orders", &good).unwrap();
+    let restored = db.table("orders").unwrap();
+    assert_eq!(restored.get_lines().len(), 2);
+    assert_eq!(restored.get(&ids[1]).unwrap().get("col1").unwrap().get().to_string(), "123");
+    
+    // The history is kept
+    let log = _git_log(&db);
+    assert_eq!(log.len(), count + 1);
+    assert_eq!(log[0], String::from("Restore table [orders] to ") + &good[..7]);
+    
+    // The table did not exist at the first commit
+    let first = db.history(".config").unwrap().last().unwrap().id.clone();
+    db.restore_table("orders", &first).unwrap();
+    assert!(!db.tables().unwrap().contains(&String::from("orders")));
+}
+
+#[test]
+fn test_restore_all() {
+    let p = "/tmp/test_restore_all";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    
+    let mut orders = db.table("orders").unwrap();
+    orders.insert(_new_test_line());
+    db.write(&mut orders).unwrap();
+    let mut inventory = db.table("inventory").unwrap();
+    inventory.insert(_new_test_line());
+    db.write(&mut inventory).unwrap();
+    let good = db.history("inventory").unwrap()[0].id.clone();
+    
+    orders.insert(_new_test_line());
+    db.write(&mut orders).unwrap();
+    db.drop("inventory").unwrap();
+    let mut customers = db.table("customers").unwrap();
+    customers.insert(_new_test_line());
+    db.write(&mut customers).unwrap();
+    db.set_use_journal(true).unwrap();
+    
+    db.restore_all(&good).unwrap();
+    let mut tables = db.tables().unwrap();
+    tables.sort();
+    assert_eq!(tables, vec!["inventory", "orders"]);
+    assert_eq!(db.table("orders").unwrap().get_lines().len(), 1);
+    assert_eq!(db.table("inventory").unwrap().get_lines().len(), 1);
+    
+    // The system tables are not restored
+    assert!(db.get_use_journal());
+    assert_eq!(_git_log(&db)[0], String::from("Restore all tables to ") + &good[..7]);
+}
+
+#[test]
+fn test_restore_all_failure() {
+    let p = "/tmp/test_restore_all_failure";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    
+    let mut inventory = db.table("inventory").unwrap();
+    inventory.insert(_new_test_line());
+    db.write(&mut inventory).unwrap();
+    let mut orders = db.table("orders").unwrap();
+    orders.insert(_new_test_line());
+    db.write(&mut orders).unwrap();
+    let good = db.history("orders").unwrap()[0].id.clone();
+    
+    inventory.insert(_new_test_line());
+    db.write(&mut inventory).unwrap();
+    orders.insert(_new_test_line());
+    db.write(&mut orders).unwrap();
+    let mut customers = db.table("customers").unwrap();
+    customers.insert(_new_test_line());
+    db.write(&mut customers).unwrap();
+    let count = _git_log(&db).len();
+    
+    // The orders cannot be written, after the inventory was staged
+    let tmp = file::tmp_path(&std::path::Path::new(p).join("orders").to_string_lossy());
+    std::fs::create_dir(&tmp).unwrap();
+    assert!(db.restore_all(&good).is_err());
+    std::fs::remove_dir(&tmp).unwrap();
+    
+    // Nothing was restored nor dropped
+    assert_eq!(_git_log(&db).len(), count);
+    assert!(git::changed_files(p).unwrap().is_empty());
+    for tbl in ["customers", "inventory", "orders"] {
+        assert_eq!(db.table(tbl).unwrap().get_lines().len(), if tbl == "customers" { 1 } else { 2 });
+    }
+    assert!(!std::path::Path::new(&file::tmp_path(&std::path::Path::new(p).join("inventory").to_string_lossy())).exists());
+    
+    db.restore_all(&good).unwrap();
+    assert_eq!(db.tables().unwrap().len(), 2);
+    assert_eq!(db.table("orders").unwrap().get_lines().len(), 1);
+}
+
+#[test]
+fn test_diff() {
+    let p = "/tmp/test_db_diff";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    
+    let mut table = db.table("customers").unwrap();
+    table.insert(_new_test_line());
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    let ids: Vec<Uuid> = table.get_lines().iter().map(|l| *l.get_id()).collect();
+    table.delete(&ids[0]);
+    table.find_by_id(&ids[1]).unwrap().get_mut("col1").unwrap().set(Type::from_int(123));
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    let changes = db.diff("customers", "HEAD~1", "HEAD").unwrap();
+    assert_eq!(changes.added.len(), 1);
+    assert_eq!(changes.removed.len(), 1);
+    assert_eq!(changes.removed[0].get_id(), &ids[0]);
+    assert_eq!(changes.changed.len(), 1);
+    assert_eq!(changes.changed[0].id, ids[1]);
+    assert_eq!(changes.changed[0].fields.len(), 1);
+    assert!(changes.changed[0].fields[0].is_type_change());
+    
+    // The table did not exist at the first revision
+    let first = db.history(".config").unwrap().last().unwrap().id.clone();
+    assert_eq!(db.diff("customers", &first, "HEAD").unwrap().added.len(), 2);
+    assert!(db.diff("customers", "HEAD", "HEAD").unwrap().is_empty());
+}
+
+#[test]
+fn test_set_merge_driver() {
+    let p = "/tmp/test_set_merge_driver";
+    let mut db = _init_db(p, true);
+    assert!(db.set_merge_driver("rental_rod_merge").is_err());
+    
+    db.set_use_git(true, None).unwrap();
+    db.set_merge_driver("rental_rod_merge").unwrap();
+    db.set_merge_driver("/usr/bin/rental_rod_merge").unwrap();
+    
+    let attributes = std::fs::read_to_string(String::from(p) + "/.gitattributes").unwrap();
+    assert_eq!(attributes, "* merge=rental_rod\n");
+    assert_eq!(_git_log(&db)[0], "Use the table merge driver");
+    
+    let output = std::process::Command::new("git")
+    .arg("-C")
+    .arg(p)
+    .arg("config")
+    .arg("merge.rental_rod.driver")
+    .output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "/usr/bin/rental_rod_merge %O %A %B %P");
+}
+
+#[test]
+fn test_git_nothing_to_commit() {
+    let p = "/tmp/test_git_nothing_to_commit";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    let count = _git_log(&db).len();
+    
+    db.git_commit("Nothing changed").unwrap();
+    db.set_use_git(true, None).unwrap();
+    assert_eq!(_git_log(&db).len(), count);
+}
+
+#[test]
+fn test_git_misconfigured_identity() {
+    let p = "/tmp/test_git_identity";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    
+    let output = std::process::Command::new("git")
+    .arg("-C")
+    .arg(p)
+    .arg("config")
+    .arg("user.name")
+    .arg("")
+    .output().unwrap();
+    assert!(output.status.success());
+    
+    let mut table = db.table("test").unwrap();
+    table.insert(_new_test_line());
+    match db.write(&mut table) {
+        Err(DbError::Git { code, stderr }) => {
+            assert_ne!(code, 0);
+            // The message comes from git, or from libgit2 with the git2 feature
+            #[cfg(not(feature = "git2"))]
+            assert!(stderr.contains("ident"), "{}", stderr);
+            #[cfg(feature = "git2")]
+            assert!(stderr.contains("signature"), "{}", stderr);
+        },
+        other => panic!("Expected a git error, got {:?}", other.err())
+    }
+    
+    // The table was written, it is committed once git is fixed
+    assert_eq!(db.table("test").unwrap().get_lines().len(), 1);
+    std::process::Command::new("git")
+    .arg("-C")
+    .arg(p)
+    .arg("config")
+    .arg("user.name")
+    .arg("Mike")
+    .output().unwrap();
+    db.git_commit("Fix the identity").unwrap();
+    assert_eq!(_git_log(&db)[0], "Fix the identity");
+}
+
+#[test]
+fn test_git_author() {
+    let p = "/tmp/test_git_author";
+    let mut db = _init_db(p, true);
+    assert_eq!(db.get_git_author_name(), "");
+    assert_eq!(db.get_git_author_email(), "");
+    assert!(db.get_git_commit_flags().is_empty());
+    
+    db.set_use_git(true, None).unwrap();
+    db.set_git_author_name("Rental Rod").unwrap();
+    db.set_git_author_email("rod@example.com").unwrap();
+    db.set_git_commit_flags(&["--date=2000-01-01T00:00:00Z", "--no-verify"]).unwrap();
+    assert!(db.set_git_commit_flags(&["--date 2000"]).is_err());
+    
+    let mut table = db.table("test").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    let commit = &db.history("test").unwrap()[0];
+    assert_eq!(commit.author, "Rental Rod");
+    assert_eq!(commit.email, "rod@example.com");
+    assert_eq!(commit.timestamp.to_rfc3339(), "2000-01-01T00:00:00+00:00");
+    
+    // The options are saved in the config
+    let mut db = _init_db(p, false);
+    assert_eq!(db.get_git_author_name(), "Rental Rod");
+    assert_eq!(db.get_git_author_email(), "rod@example.com");
+    assert_eq!(db.get_git_commit_flags(), ["--date=2000-01-01T00:00:00Z", "--no-verify"]);
+    
+    db.set_git_commit_flags(&[]).unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    let commit = &db.history("test").unwrap()[0];
+    assert_eq!(commit.author, "Rental Rod");
+    assert!(commit.timestamp.timestamp() > 946684800);
+}
+
+#[test]
+fn test_branches() {
+    let p = "/tmp/test_branches";
+    let mut db = _init_db(p, true);
+    assert!(db.create_branch("edits").is_err());
+    db.set_use_git(true, None).unwrap();
+    let main = db.get_branch().unwrap();
+    
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    let ids: Vec<Uuid> = table.get_lines().iter().map(|l| *l.get_id()).collect();
+    
+    // Edit the table on a branch
+    db.create_branch("edits").unwrap();
+    db.checkout_branch("edits").unwrap();
+    assert_eq!(db.get_branch().unwrap(), "edits");
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&ids[0]).unwrap().get_mut("col1").unwrap().set(Type::from_str("edited"));
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    let mut customers = db.table("customers").unwrap();
+    customers.insert(_new_test_line());
+    db.write(&mut customers).unwrap();
+    
+    // The same line is changed on the main branch, but not the same field
+    db.checkout_branch(&main).unwrap();
+    assert_eq!(db.table("orders").unwrap().get_lines().len(), 2);
+    assert_eq!(db.tables().unwrap().len(), 1);
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&ids[0]).unwrap().get_mut("col2").unwrap().set(Type::from_str("main"));
+    table.delete(&ids[1]);
+    db.write(&mut table).unwrap();
+    
+    assert!(db.merge_conflicts("edits").unwrap().is_empty());
+    db.merge_branch("edits").unwrap();
+    let mut table = db.table("orders").unwrap();
+    assert_eq!(table.get_lines().len(), 2);
+    let line = table.find_by_id(&ids[0]).unwrap();
+    assert_eq!(line.get("col1").unwrap().get().to_string(), "edited");
+    assert_eq!(line.get("col2").unwrap().get().to_string(), "main");
+    assert_eq!(db.table("customers").unwrap().get_lines().len(), 1);
+    
+    // It is a merge commit
+    assert_eq!(_git_log(&db)[0], "Merge branch [edits]");
+    let output = std::process::Command::new("git")
+    .arg("-C")
+    .arg(p)
+    .arg("rev-list")
+    .arg("--parents")
+    .arg("-1")
+    .arg("HEAD")
+    .output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap().split_whitespace().count(), 3);
+    
+    // Nothing left to merge
+    let count = _git_log(&db).len();
+    db.merge_branch("edits").unwrap();
+    assert_eq!(_git_log(&db).len(), count);
+}
+
+#[test]
+fn test_merge_branch_conflict() {
+    let p = "/tmp/test_merge_branch_conflict";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    let main = db.get_branch().unwrap();
+    
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    let id = *table.get_lines()[0].get_id();
+    
+    db.create_branch("edits").unwrap();
+    db.checkout_branch("edits").unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col1").unwrap().set(Type::from_str("edits"));
+    db.write(&mut table).unwrap();
+    
+    db.checkout_branch(&main).unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col1").unwrap().set(Type::from_str("main"));
+    db.write(&mut table).unwrap();
+    let count = _git_log(&db).len();
+    
+    let conflicts = db.merge_conflicts("edits").unwrap();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].0, "orders");
+    assert_eq!(conflicts[0].1.fields, vec!["col1"]);
+    
+    assert!(matches!(db.merge_branch("edits"), Err(DbError::MergeConflict(tables)) if tables == vec!["orders"]));
+    assert_eq!(_git_log(&db).len(), count);
+    let mut table = db.table("orders").unwrap();
+    assert_eq!(table.find_by_id(&id).unwrap().get("col1").unwrap().get().to_string(), "main");
+}
+
+#[test]
+fn test_merge_branch_uncommitted() {
+    let p = "/tmp/test_merge_branch_uncommitted";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    let main = db.get_branch().unwrap();
+    
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    db.create_branch("edits").unwrap();
+    db.checkout_branch("edits").unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    db.checkout_branch(&main).unwrap();
+    let mut table = db.table("customers").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    // A line is written without being committed
+    let mut manager = table_manager::get_table_manager(p, "orders", &Options::default()).unwrap();
+    let mut table = manager.read().unwrap();
+    table.insert(_new_test_line());
+    manager.write(&mut table, false).unwrap();
+    let count = _git_log(&db).len();
+    
+    assert!(matches!(db.merge_branch("edits"), Err(DbError::Uncommitted(files)) if files == vec!["orders"]));
+    assert_eq!(_git_log(&db).len(), count);
+    assert_eq!(db.table("orders").unwrap().get_lines().len(), 2);
+    assert!(!std::path::Path::new(p).join(".git/MERGE_HEAD").exists());
+}
+
+#[test]
+fn test_merge_branch_failure() {
+    let p = "/tmp/test_merge_branch_failure";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    let main = db.get_branch().unwrap();
+    
+    for tbl in ["customers", "orders"] {
+        let mut table = db.table(tbl).unwrap();
+        table.insert(_new_test_line());
+        db.write(&mut table).unwrap();
+    }
+    
+    db.create_branch("edits").unwrap();
+    db.checkout_branch("edits").unwrap();
+    for tbl in ["customers", "orders"] {
+        let mut table = db.table(tbl).unwrap();
+        table.insert(_new_test_line());
+        db.write(&mut table).unwrap();
+    }
+    
+    db.checkout_branch(&main).unwrap();
+    let mut table = db.table("items").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    let count = _git_log(&db).len();
+    let unchanged = |db: &Db| {
+        assert_eq!(_git_log(db).len(), count);
+        assert!(!std::path::Path::new(p).join(".git/MERGE_HEAD").exists());
+        assert!(git::changed_files(p).unwrap().is_empty());
+        for tbl in ["customers", "orders"] {
+            assert_eq!(db.table(tbl).unwrap().get_lines().len(), 1);
+        }
+    };
+    
+    // The orders cannot be written, after the customers were merged
+    let tmp = file::tmp_path(&std::path::Path::new(p).join("orders").to_string_lossy());
+    std::fs::create_dir(&tmp).unwrap();
+    assert!(db.merge_branch("edits").is_err());
+    unchanged(&db);
+    std::fs::remove_dir(&tmp).unwrap();
+    
+    // The tables are written, but the merge cannot be committed
+    _git(p, &["config", "user.name", ""]);
+    assert!(matches!(db.merge_branch("edits"), Err(DbError::Git { .. })));
+    unchanged(&db);
+    
+    _git(p, &["config", "user.name", "Mike"]);
+    db.merge_branch("edits").unwrap();
+    assert_eq!(_git_log(&db)[0], "Merge branch [edits]");
+    for tbl in ["customers", "orders"] {
+        assert_eq!(db.table(tbl).unwrap().get_lines().len(), 2);
+    }
+}
+
+#[test]
+fn test_merge_branch_new_empty_table() {
+    let p = "/tmp/test_merge_branch_new_empty_table";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    let main = db.get_branch().unwrap();
+    
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    // The other branch creates a table without any line
+    db.create_branch("edits").unwrap();
+    db.checkout_branch("edits").unwrap();
+    let mut table = db.table("customers").unwrap();
+    db.write(&mut table).unwrap();
+    
+    db.checkout_branch(&main).unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    db.merge_branch("edits").unwrap();
+    assert_eq!(_git_log(&db)[0], "Merge branch [edits]");
+    let mut tables = db.tables().unwrap();
+    tables.sort();
+    assert_eq!(tables, vec!["customers", "orders"]);
+    assert!(git::changed_files(p).unwrap().is_empty());
+}
+
+#[test]
+fn test_merge_branch_fast_forward() {
+    let p = "/tmp/test_merge_branch_ff";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    let main = db.get_branch().unwrap();
+    
+    db.create_branch("edits").unwrap();
+    db.checkout_branch("edits").unwrap();
+    db.set_use_journal(true).unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    db.checkout_branch(&main).unwrap();
+    assert!(!db.get_use_journal());
+    assert!(db.tables().unwrap().is_empty());
+    
+    db.merge_branch("edits").unwrap();
+    assert_eq!(db.table("orders").unwrap().get_lines().len(), 1);
+    assert!(db.get_use_journal());
+    assert_eq!(_git_log(&db)[0], "Create table [orders]: +1 line");
+}
+
+#[test]
+fn test_sync() {
+    let remote = "/tmp/test_sync_remote.git";
+    let a = "/tmp/test_sync_a";
+    let b = "/tmp/test_sync_b";
+    for p in [remote, b] {
+        if std::path::Path::new(p).exists() {
+            std::fs::remove_dir_all(p).unwrap();
+        }
+    }
+    _git(".", &["init", "--quiet", "--bare", remote]);
+    
+    let mut db_a = _init_db(a, true);
+    db_a.set_use_git(true, None).unwrap();
+    let mut table = db_a.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db_a.write(&mut table).unwrap();
+    let id = *table.get_lines()[0].get_id();
+    db_a.sync(remote).unwrap();
+    
+    // Another machine gets the database
+    _git(".", &["clone", "--quiet", remote, b]);
+    let mut db_b = Db::new(b).unwrap();
+    assert!(db_b.get_use_git());
+    assert_eq!(db_b.table("orders").unwrap().get_lines().len(), 1);
+    
+    // Both change the same line, but not the same field
+    let mut table = db_b.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col1").unwrap().set(Type::from_str("b"));
+    db_b.write(&mut table).unwrap();
+    db_b.sync(remote).unwrap();
+    
+    let mut table = db_a.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col2").unwrap().set(Type::from_str("a"));
+    table.insert(_new_test_line());
+    db_a.write(&mut table).unwrap();
+    db_a.sync(remote).unwrap();
+    
+    db_b.sync(remote).unwrap();
+    for db in [&db_a, &db_b] {
+        let mut table = db.table("orders").unwrap();
+        assert_eq!(table.get_lines().len(), 2);
+        let line = table.find_by_id(&id).unwrap();
+        assert_eq!(line.get("col1").unwrap().get().to_string(), "b");
+        assert_eq!(line.get("col2").unwrap().get().to_string(), "a");
+    }
+    
+    // Both change the same field
+    let mut table = db_b.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col3").unwrap().set(Type::from_str("b"));
+    db_b.write(&mut table).unwrap();
+    db_b.sync(remote).unwrap();
+    
+    let mut table = db_a.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col3").unwrap().set(Type::from_str("a"));
+    db_a.write(&mut table).unwrap();
+    assert!(matches!(db_a.sync(remote), Err(DbError::MergeConflict(tables)) if tables == vec!["orders"]));
+    
+    // Nothing was pushed
+    db_b.sync(remote).unwrap();
+    let mut table = db_b.table("orders").unwrap();
+    assert_eq!(table.find_by_id(&id).unwrap().get("col3").unwrap().get().to_string(), "b");
+}
+
+#[test]
+fn test_sync_diverged() {
+    let remote = "/tmp/test_sync_diverged.git";
+    if std::path::Path::new(remote).exists() {
+        std::fs::remove_dir_all(remote).unwrap();
+    }
+    _git(".", &["init", "--quiet", "--bare", remote]);
+    
+    let mut db_a = _init_db("/tmp/test_sync_diverged_a", true);
+    db_a.set_use_git(true, None).unwrap();
+    let mut db_b = _init_db("/tmp/test_sync_diverged_b", true);
+    db_b.set_use_git(true, Some("Another database")).unwrap();
+    
+    db_a.sync(remote).unwrap();
+    assert!(matches!(db_b.sync(remote), Err(DbError::Diverged(r)) if r == remote));
+    assert!(db_b.sync("/tmp/test_sync_does_not_exist.git").is_err());
+}
+
+#[test]
+fn test_blame() {
+    let p = "/tmp/test_blame";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    let id = *table.get_lines()[0].get_id();
+    let other = *table.get_lines()[1].get_id();
+    
+    db.set_git_author_name("Mike").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col2").unwrap().set(Type::from_str("changed"));
+    db.write(&mut table).unwrap();
+    
+    // Changing another line does not change the blame
+    db.set_git_author_name("Sean").unwrap();
+    table.find_by_id(&other).unwrap().get_mut("col1").unwrap().set(Type::from_str("changed"));
+    db.write(&mut table).unwrap();
+    
+    db.set_use_journal(true).unwrap();
+    table.find_by_id(&id).unwrap().add("col4", Type::from_int(4)).unwrap();
+    db.write(&mut table).unwrap();
+    
+    let history = db.history("orders").unwrap();
+    let blame = db.blame("orders", &id).unwrap();
+    assert_eq!(blame.created.id, history[3].id);
+    let fields: Vec<(&str, &str)> = blame.fields.iter().map(|(f, c)| (f.as_str(), c.id.as_str())).collect();
+    assert_eq!(fields, vec![
+        ("col1", history[3].id.as_str()),
+        ("col2", history[2].id.as_str()),
+        ("col3", history[3].id.as_str()),
+        ("col4", history[0].id.as_str()),
+    ]);
+    assert_eq!(blame.fields[1].1.author, "Mike");
+    assert_eq!(blame.fields[3].1.author, "Sean");
+    
+    assert!(db.blame("orders", &Uuid::new_v4()).is_err());
+}
+
+#[test]
+fn test_blame_merge_branch() {
+    let p = "/tmp/test_blame_merge_branch";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    let main = db.get_branch().unwrap();
+    
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    let id = *table.get_lines()[0].get_id();
+    
+    db.create_branch("edits").unwrap();
+    db.checkout_branch("edits").unwrap();
+    db.set_git_author_name("Mike").unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col1").unwrap().set(Type::from_str("edits"));
+    db.write(&mut table).unwrap();
+    
+    db.checkout_branch(&main).unwrap();
+    db.set_git_author_name("Sean").unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col2").unwrap().set(Type::from_str("main"));
+    db.write(&mut table).unwrap();
+    
+    db.set_git_author_name("Bob").unwrap();
+    db.merge_branch("edits").unwrap();
+    
+    // Each field keeps the commit of the branch that changed it, not the merge
+    let history = db.history("orders").unwrap();
+    let blame = db.blame("orders", &id).unwrap();
+    assert_eq!(blame.created.id, history[history.len() - 1].id);
+    let authors: Vec<(&str, &str)> = blame.fields.iter().map(|(f, c)| (f.as_str(), c.author.as_str())).collect();
+    assert_eq!(authors[..2], [("col1", "Mike"), ("col2", "Sean")]);
+    assert_eq!(blame.fields[2].1.id, blame.created.id);
+    assert!(blame.fields.iter().all(|(_, c)| c.id != history[0].id));
+}
+
+#[test]
+fn test_batch() {
+    let p = "/tmp/test_batch";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    let count = _git_log(&db).len();
+    assert!(db.commit_batch("Nothing").is_err());
+    
+    db.begin_batch().unwrap();
+    assert!(db.in_batch());
+    assert!(db.begin_batch().is_err());
+    for name in ["a", "b", "c"] {
+        let mut table = db.table(name).unwrap();
+        table.insert(_new_test_line());
+        db.write(&mut table).unwrap();
+    }
+    db.drop("b").unwrap();
+    assert!(db.snapshot("during").is_err());
+    
+    // The tables are written, but not committed
+    assert_eq!(db.table("a").unwrap().get_lines().len(), 1);
+    assert_eq!(_git_log(&db).len(), count);
+    
+    db.commit_batch("Import").unwrap();
+    assert!(!db.in_batch());
+    let log = _git_log(&db);
+    assert_eq!(log.len(), count + 1);
+    assert_eq!(log[0], "Import");
+    
+    let output = std::process::Command::new("git")
+    .arg("-C")
+    .arg(p)
+    .arg("log")
+    .arg("-1")
+    .arg("--format=%b")
+    .output().unwrap();
+    let body = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(body.trim(), "Create table [a]: +1 line\nCreate table [b]: +1 line\nCreate table [c]: +1 line\nDrop table [b]");
+    
+    // Back to one commit per write
+    let mut table = db.table("a").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    assert_eq!(_git_log(&db).len(), count + 2);
+}
+
+#[test]
+fn test_transaction_single_commit() {
+    let p = "/tmp/test_transaction_git";
+    let mut db = _init_db(p, true);
+    db.set_use_git(true, None).unwrap();
+    let count = _git_log(&db).len();
+    
+    let mut orders = db.table("orders").unwrap();
+    let mut inventory = db.table("inventory").unwrap();
+    orders.insert(_new_test_line());
+    inventory.insert(_new_test_line());
+    
+    let mut tx = db.transaction();
+    tx.write(&mut orders).unwrap();
+    tx.write(&mut inventory).unwrap();
+    tx.commit().unwrap();
+    
+    let log = _git_log(&db);
+    assert_eq!(log.len(), count + 1);
+    assert_eq!(log[0], "Transaction");
+    
+    let output = std::process::Command::new("git")
+    .arg("-C")
+    .arg(p)
+    .arg("log")
+    .arg("-1")
+    .arg("--format=%b")
+    .output().unwrap();
+    let body = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(body.trim(), "Create table [inventory]: +1 line\nCreate table [orders]: +1 line");
+}
+
+#[test]
+fn test_use_journal() {
+    let p = "/tmp/test_use_journal";
+    let mut db = _init_db(p, true);
+    assert!(!db.get_use_journal());
+    
+    db.set_use_journal(true).unwrap();
+    assert!(db.get_use_journal());
+    
+    let mut table = db.table("journal").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    let journal_path = std::path::Path::new(p).join(".journal.journal");
+    assert!(!journal_path.exists());
+    
+    // Insert, update and delete
+    let id = *table.get_lines()[0].get_id();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col1").unwrap().set(Type::from_int(1));
+    db.write(&mut table).unwrap();
+    let inserted = *table.get_lines()[1].get_id();
+    table.delete(&inserted);
+    db.write(&mut table).unwrap();
+    assert!(journal_path.exists());
+    
+    let db = _init_db(p, false);
+    assert!(db.get_use_journal());
+    let mut table = db.table("journal").unwrap();
+    assert_eq!(table.get_lines().len(), 1);
+    assert_eq!(table.find_by_id(&id).unwrap().get("col1").unwrap().get(), &Type::from_int(1));
+    
+    // The journal is folded back into the table file once it gets too big
+    for i in 0..100 {
+        table.find_by_id(&id).unwrap().get_mut("col1").unwrap().set(Type::from_int(i));
+        db.write(&mut table).unwrap();
+    }
+    assert!(std::fs::metadata(&journal_path).map(|m| m.len()).unwrap_or(0) <= 4096);
+    assert_eq!(db.table("journal").unwrap().find_by_id(&id).unwrap().get("col1").unwrap().get(), &Type::from_int(99));
+    
+    // Writing without the journal folds it back into the table file
+    let mut db = _init_db(p, false);
+    db.set_use_journal(false).unwrap();
+    let mut table = db.table("journal").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    assert!(!journal_path.exists());
+    assert_eq!(db.table("journal").unwrap().get_lines().len(), 2);
+}
+
+#[test]
+fn test_journal_conflict() {
+    let p = "/tmp/test_journal_conflict";
+    let mut db = _init_db(p, true);
+    db.set_use_journal(true).unwrap();
+    
+    let mut table = db.table("journal").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    let mut other = db.table("journal").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    other.insert(_new_test_line());
+    assert!(matches!(db.write(&mut other), Err(DbError::Conflict(_))));
+    
+    db.force_write(&mut other).unwrap();
+    assert_eq!(db.table("journal").unwrap().get_lines().len(), 2);
+}
+
+#[test]
+fn test_compact() {
+    let p = "/tmp/test_compact";
+    let mut db = _init_db(p, true);
+    db.set_use_journal(true).unwrap();
+    db.set_use_git(true, None).unwrap();
+    
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_test_line());
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    let id = *table.get_lines()[0].get_id();
+    table.delete(&id);
+    db.write(&mut table).unwrap();
+    
+    let mut table = db.table("inventory").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    let count = _git_log(&db).len();
+    let reclaimed = db.compact_all().unwrap();
+    assert_eq!(reclaimed.len(), 2);
+    for (tbl, bytes) in reclaimed {
+        match tbl.as_str() {
+            "orders" => assert!(bytes > 0),
+            "inventory" => assert_eq!(bytes, 0),
+            _ => panic!("Unexpected table [{}]", tbl)
+        }
+    }
+    
+    assert!(!std::path::Path::new(p).join(".orders.journal").exists());
+    assert_eq!(db.table("orders").unwrap().get_lines().len(), 1);
+    
+    let log = _git_log(&db);
+    assert_eq!(log.len(), count + 1);
+    assert_eq!(log[0], "Compact table [orders]");
+    
+    assert_eq!(db.compact("orders").unwrap(), 0);
+    assert_eq!(db.compact("does_not_exist").unwrap(), 0);
+}
+
+#[test]
+fn test_lock_timeout() {
+    let p = "/tmp/test_lock_timeout";
+    let mut db = _init_db(p, true);
+    db.set_lock_timeout(Duration::from_millis(20));
+    assert_eq!(db.get_lock_timeout(), Duration::from_millis(20));
+    
+    let mut table = db.table("locked").unwrap();
+    table.insert(_new_test_line());
+    db.write(&mut table).unwrap();
+    
+    // Another process is writing to the table
+    let tbl_path = std::path::Path::new(p).join("locked");
+    let lock = crate::util::lock::Lock::exclusive(tbl_path.to_str().unwrap(), Duration::ZERO).unwrap();
+    
+    assert!(matches!(db.table("locked"), Err(DbError::Locked(_))));
+    assert!(matches!(db.write(&mut table), Err(DbError::Locked(_))));
+    assert!(matches!(db.drop("locked"), Err(DbError::Locked(_))));
+    
+    drop(lock);
+    assert_eq!(db.table("locked").unwrap().get_lines().len(), 1);
+}
+
+#[test]
+fn test_write_conflict() {
+    let p = "/tmp/test_write_conflict";
+    let db = _init_db(p, true);
+    
+    let mut first = db.table("conflict").unwrap();
+    let mut second = db.table("conflict").unwrap();
+    assert_eq!(first.get_version(), None);
+    
+    first.insert(_new_test_line());
+    db.write(&mut first).unwrap();
+    assert!(first.get_version().is_some());
+    
+    // The second table was read before the first one was written
+    second.insert(_new_test_line());
+    assert!(matches!(db.write(&mut second), Err(DbError::Conflict(name)) if name == "conflict"));
+    assert_eq!(db.table("conflict").unwrap().get_lines().len(), 1);
+    
+    // A table can be written multiple times
+    first.insert(_new_test_line());
+    db.write(&mut first).unwrap();
+    assert_eq!(db.table("conflict").unwrap().get_lines().len(), 2);
+    
+    // Reading the table again solves the conflict
+    let mut third = db.table("conflict").unwrap();
+    third.insert(_new_test_line());
+    db.write(&mut third).unwrap();
+    assert_eq!(db.table("conflict").unwrap().get_lines().len(), 3);
+    
+    // Forcing the write loses the changes made by the others
+    assert!(matches!(db.write(&mut second), Err(DbError::Conflict(_))));
+    db.force_write(&mut second).unwrap();
+    assert_eq!(db.table("conflict").unwrap().get_lines().len(), 1);
+    
+    db.write(&mut second).unwrap();
+    assert!(matches!(db.write(&mut first), Err(DbError::Conflict(_))));
+}
+
+#[test]
+fn test_table_versions() {
+    let p = "/tmp/test_table_versions";
+    let db = _init_db(p, true);
+    
+    // The format of a table is detected from its file
+    std::fs::write(std::path::Path::new(p).join("orders"), "#v2.0#\n@fields\n@options\n").unwrap();
+    let mut orders = db.table("orders").unwrap();
+    orders.insert(_new_test_line());
+    db.write(&mut orders).unwrap();
+    
+    let mut inventory = db.table("inventory").unwrap();
+    inventory.insert(_new_test_line());
+    db.write(&mut inventory).unwrap();
+    
+    let content = std::fs::read_to_string(std::path::Path::new(p).join("orders")).unwrap();
+    assert!(content.starts_with("#v2.0#\n@fields col1:string col2:string col3:string\n@options\n"));
+    assert_eq!(content.lines().count(), 4);
+    assert!(std::fs::read_to_string(std::path::Path::new(p).join("inventory")).unwrap().starts_with("#v1.0#\n"));
+    
+    assert_eq!(db.table("orders").unwrap().get_lines(), orders.get_lines());
+    assert_eq!(db.table("inventory").unwrap().get_lines(), inventory.get_lines());
+}
+
+#[test]
+fn test_upgrade_table() {
+    let p = "/tmp/test_upgrade_table";
+    let mut db = _init_db(p, true);
+    db.set_use_journal(true).unwrap();
+    db.set_use_git(true, None).unwrap();
+    
+    let mut orders = db.table("orders").unwrap();
+    let mut line = _new_test_line();
+    line.add("note", Type::from_str("Crème \"brûlée\"\nà emporter")).unwrap();
+    orders.insert(line);
+    orders.insert(_new_test_line());
+    db.write(&mut orders).unwrap();
+    // Some changes are only in the journal
+    orders.insert(_new_test_line());
+    db.write(&mut orders).unwrap();
+    
+    let mut inventory = db.table("inventory").unwrap();
+    inventory.insert(_new_test_line());
+    db.write(&mut inventory).unwrap();
+    
+    assert!(db.upgrade_table("orders", 2).unwrap());
+    assert!(!std::path::Path::new(p).join(".orders.journal").exists());
+    assert!(std::fs::read_to_string(std::path::Path::new(p).join("orders")).unwrap().starts_with("#v2.0#\n"));
+    assert_eq!(db.table("orders").unwrap().get_lines(), orders.get_lines());
+    assert_eq!(_git_log(&db)[0], "Upgrade table [orders] to v2");
+    
+    // Nothing to do
+    let count = _git_log(&db).len();
+    assert!(!db.upgrade_table("orders", 2).unwrap());
+    assert_eq!(_git_log(&db).len(), count);
+    
+    assert!(db.upgrade_table("orders", 1).is_err());
+    assert!(db.upgrade_table("orders", 3).is_err());
+    assert!(db.upgrade_table("does_not_exist", 2).is_err());
+    
+    // The table was written since it was read
+    let mut stale = db.table("inventory").unwrap();
+    inventory.insert(_new_test_line());
+    db.write(&mut inventory).unwrap();
+    stale.insert(_new_test_line());
+    
+    assert_eq!(db.upgrade_all().unwrap(), vec!["inventory"]);
+    assert_eq!(_git_log(&db)[0], "Upgrade table [inventory] to v2");
+    assert_eq!(db.table("inventory").unwrap().get_lines(), inventory.get_lines());
+    assert!(matches!(db.write(&mut stale), Err(DbError::Conflict(_))));
+    assert!(db.upgrade_all().unwrap().is_empty());
+}
+
+
+fn _init_db(p: &str, fresh: bool) -> Db {
+    if std::path::Path::new(p).exists() && fresh {
+        std::fs::remove_dir_all(p).unwrap();
+    }
+    
+    Db::new(p).unwrap()
+}
+
+fn _new_test_line() -> Line {
+    let mut line = Line::new();
+    
+    line.add("col1", Type::from_str("123")).unwrap();
+    line.add("col2", Type::from_str("456")).unwrap();
+    line.add("col3", Type::from_str("789")).unwrap();
+    
+    line
+}
+
+fn _git_log(db: &Db) -> Vec<String> {
+    let mut formatted_lines = vec![];
+    
+    if db.git_exists() {
+        let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(&db.path)
+        .arg("log")
+        .arg("--oneline")
+        .output().unwrap();
+        
         
         let lines = String::from_utf8(output.stdout).unwrap();
         
@@ -581,6 +2500,16 @@ fn _git_log(db: &Db) -> Vec<String> {
     formatted_lines
 }
 
+fn _git(p: &str, args: &[&str]) {
+    let output = std::process::Command::new("git")
+    .arg("-C")
+    .arg(p)
+    .args(args)
+    .output().unwrap();
+    
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
 fn _get_first_space(str: &str) -> usize {
     let mut index = 0;
     for c in str.chars() {
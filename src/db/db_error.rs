@@ -12,4 +12,34 @@ pub enum DbError {
 
     #[error(transparent)]
     IoError(#[from] std::io::Error),
+
+    #[error("Could not lock [{0}], it is used by another process")]
+    Locked(String),
+
+    #[error("The table [{0}] was modified since it was read")]
+    Conflict(String),
+
+    /**
+     * A git command failed. The code is -1 if git was killed by a signal
+     */
+    #[error("git failed with exit code {code}: {stderr}")]
+    Git { code: i32, stderr: String },
+
+    /**
+     * Both sides of a merge changed the same fields of these tables (see Db::merge_conflicts)
+     */
+    #[error("The merge has conflicts in the tables {0:?}")]
+    MergeConflict(Vec<String>),
+
+    /**
+     * The database and the remote have no common history, they cannot be merged
+     */
+    #[error("The database and the remote [{0}] have no common history")]
+    Diverged(String),
+
+    /**
+     * These files changed since the last commit, the operation would lose the changes
+     */
+    #[error("The files {0:?} have changes that are not committed")]
+    Uncommitted(Vec<String>),
 }
@@ -0,0 +1,226 @@
+//! Everything related to the differences between two versions of a table
+//!
+//! The lines are matched by id. A line can be added, removed or changed,
+//! and for a changed line, every field that changed is reported.
+//!
+//! # Example
+//! ```
+//! use rental_rod::db::{diff, field_type::Type, line::Line, table::Table};
+//!
+//! let mut line = Line::new();
+//! line.add("age", Type::from_str("12")).unwrap();
+//! let before = Table::new("customers", vec![line.clone()]).unwrap();
+//!
+//! line.get_mut("age").unwrap().set(Type::from_int(12));
+//! let after = Table::new("customers", vec![line]).unwrap();
+//!
+//! let changes = diff::diff(&before, &after);
+//! assert!(changes.changed[0].fields[0].is_type_change());
+//! ```
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use super::{field::Field, field_type::Type, line::Line, table::Table};
+
+/**
+ * The differences between two versions of a table (see diff)
+ */
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct TableDiff {
+    /**
+     * The lines that only exist in the new version
+     */
+    pub added: Vec<Line>,
+    /**
+     * The lines that only exist in the old version
+     */
+    pub removed: Vec<Line>,
+    /**
+     * The lines that exist in both versions, but with different fields
+     */
+    pub changed: Vec<LineDiff>
+}
+
+impl TableDiff {
+    /**
+     * True if both versions have the same lines
+     */
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/**
+ * The fields that changed on a line
+ */
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct LineDiff {
+    pub id: Uuid,
+    pub fields: Vec<FieldChange>
+}
+
+/**
+ * A change to a field of a line
+ */
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum FieldChange {
+    /**
+     * The field only exists in the new version of the line
+     */
+    Added(Field),
+    /**
+     * The field only exists in the old version of the line
+     */
+    Removed(Field),
+    /**
+     * The value of the field changed (maybe its type too)
+     */
+    Changed {
+        name: String,
+        before: Type,
+        after: Type
+    }
+}
+
+impl FieldChange {
+    /**
+     * Name of the field that changed
+     */
+    pub fn get_name(&self) -> &str {
+        match self {
+            FieldChange::Added(field) => field.get_name(),
+            FieldChange::Removed(field) => field.get_name(),
+            FieldChange::Changed { name, .. } => name
+        }
+    }
+
+    /**
+     * True if the value changed from one type to another (e.g. from a String to an Integer)
+     */
+    pub fn is_type_change(&self) -> bool {
+        match self {
+            FieldChange::Changed { before, after, .. } => before.get_type() != after.get_type(),
+            _ => false
+        }
+    }
+}
+
+/**
+ * Return the differences between two versions of a table
+ */
+pub fn diff(before: &Table, after: &Table) -> TableDiff {
+    let before_lines = before.get_lines();
+    let after_lines = after.get_lines();
+    let before_by_id: HashMap<&Uuid, &Line> = before_lines.iter().map(|l| (l.get_id(), *l)).collect();
+    let after_by_id: HashMap<&Uuid, &Line> = after_lines.iter().map(|l| (l.get_id(), *l)).collect();
+
+    let mut table_diff = TableDiff { added: Vec::new(), removed: Vec::new(), changed: Vec::new() };
+    for line in &after_lines {
+        match before_by_id.get(line.get_id()) {
+            None => table_diff.added.push((*line).clone()),
+            Some(old) => {
+                let fields = diff_lines(old, line);
+                if !fields.is_empty() {
+                    table_diff.changed.push(LineDiff { id: *line.get_id(), fields });
+                }
+            }
+        }
+    }
+
+    for line in &before_lines {
+        if !after_by_id.contains_key(line.get_id()) {
+            table_diff.removed.push((*line).clone());
+        }
+    }
+
+    table_diff
+}
+
+/**
+ * Return the changes to the fields between two versions of a line
+ */
+pub fn diff_lines(before: &Line, after: &Line) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    for field in after.get_fields() {
+        match before.get(field.get_name()) {
+            None => changes.push(FieldChange::Added(field.clone())),
+            Some(old) => {
+                if old.get() != field.get() {
+                    changes.push(FieldChange::Changed {
+                        name: String::from(field.get_name()),
+                        before: old.get().clone(),
+                        after: field.get().clone()
+                    });
+                }
+            }
+        }
+    }
+
+    for field in before.get_fields() {
+        if after.get(field.get_name()).is_none() {
+            changes.push(FieldChange::Removed(field.clone()));
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use crate::db::{field::Field, field_type::Type, line::Line, table::Table};
+
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let kept = Uuid::new_v4();
+        let changed = Uuid::new_v4();
+        let removed = Uuid::new_v4();
+        let added = Uuid::new_v4();
+
+        let before = Table::new("customers", vec![
+            Line::new_with_id(kept, vec![Field::new_str("name", "Mike")]),
+            Line::new_with_id(changed, vec![Field::new_str("name", "Sean"), Field::new_str("age", "12"), Field::new_bool("vip", true)]),
+            Line::new_with_id(removed, vec![Field::new_str("name", "Bob")]),
+        ]).unwrap();
+        let after = Table::new("customers", vec![
+            Line::new_with_id(kept, vec![Field::new_str("name", "Mike")]),
+            Line::new_with_id(changed, vec![Field::new_str("name", "Simon"), Field::new_int("age", 12), Field::new_str("city", "Paris")]),
+            Line::new_with_id(added, vec![Field::new_str("name", "Paul")]),
+        ]).unwrap();
+
+        let table_diff = diff(&before, &after);
+        assert_eq!(table_diff.added.len(), 1);
+        assert_eq!(table_diff.added[0].get_id(), &added);
+        assert_eq!(table_diff.removed.len(), 1);
+        assert_eq!(table_diff.removed[0].get_id(), &removed);
+
+        assert_eq!(table_diff.changed.len(), 1);
+        assert_eq!(table_diff.changed[0].id, changed);
+        assert_eq!(table_diff.changed[0].fields, vec![
+            FieldChange::Changed { name: String::from("name"), before: Type::from_str("Sean"), after: Type::from_str("Simon") },
+            FieldChange::Changed { name: String::from("age"), before: Type::from_str("12"), after: Type::from_int(12) },
+            FieldChange::Added(Field::new_str("city", "Paris")),
+            FieldChange::Removed(Field::new_bool("vip", true)),
+        ]);
+
+        let fields = &table_diff.changed[0].fields;
+        assert!(!fields[0].is_type_change());
+        assert!(fields[1].is_type_change());
+        assert_eq!(fields[1].get_name(), "age");
+        assert!(!fields[2].is_type_change());
+    }
+
+    #[test]
+    fn test_diff_same_table() {
+        let table = Table::new("customers", vec![Line::new_with_id(Uuid::new_v4(), vec![Field::new_str("name", "Mike")])]).unwrap();
+
+        assert!(diff(&table, &table).is_empty());
+    }
+}
@@ -2,9 +2,18 @@
 use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
 
+use super::db_error::DbError;
 use super::field_type::Type;
 
+/// Name of the id of a line in the files, it cannot be used by a field
+pub const ID_NAME: &str = "_id";
+
+/// Characters used by the file format to separate the names from the values
+const RESERVED_CHARS: [char; 6] = [' ', '[', ']', '"', '#', ':'];
+
 #[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone)]
 /**
  * Represent a Single cell from a Table
  * 
@@ -82,4 +91,22 @@ impl Field {
         &self.name
     }
 
+    /**
+     * Check that the name can be written to a table file
+     *
+     * A name cannot be empty, be ID_NAME, contain a whitespace or one of the
+     * characters used by the file format: [ ] " # :
+     */
+    pub fn check_name(name: &str) -> Result<(), DbError> {
+        if name.is_empty() || name == ID_NAME {
+            return Err(DbError::Custom(String::from("Invalid field name [") + name + "]"));
+        }
+
+        if let Some(c) = name.chars().find(|c| c.is_whitespace() || RESERVED_CHARS.contains(c)) {
+            return Err(DbError::Custom(format!("Invalid field name [{}], it cannot contain {:?}", name, c)));
+        }
+
+        Ok(())
+    }
+
 }
\ No newline at end of file
@@ -1,4 +1,6 @@
 //! This contains all the implementation of the Type (which contains values)
+use std::str::FromStr;
+
 use chrono::{Utc, DateTime};
 use rust_decimal::Decimal;
 
@@ -132,6 +134,28 @@ impl Type {
         }.to_owned()
     }
 
+    /**
+     * Build a Type from the name of its type (see get_type) and its value (see to_string)
+     */
+    pub fn parse(type_name: &str, value: &str) -> Result<Type, DbError> {
+        let invalid = || DbError::Custom(String::from("Error parsing [") + value + "] into a " + type_name);
+
+        let t = match type_name {
+            "string" => Type::String(String::from(value)),
+            "integer" => Type::Integer(i64::from_str(value).map_err(|_| invalid())?),
+            "decimal" => Type::Decimal(Decimal::from_str(value).map_err(|_| invalid())?),
+            "boolean" => match value {
+                "true" => Type::Boolean(true),
+                "false" => Type::Boolean(false),
+                _ => return Err(invalid())
+            },
+            "datetime" => Type::DateTime(DateTime::from_str(value).map_err(|_| invalid())?),
+            _ => return Err(DbError::Custom(String::from("The type [") + type_name + "] is not supported"))
+        };
+
+        Ok(t)
+    }
+
     /**
      * Convert ANY part of type to a String
      */
@@ -181,6 +205,21 @@ mod test {
         assert_eq!(datetime.to_string(), "2020-11-14T10:20:30.149Z");
     }
 
+    #[test]
+    fn test_parse() {
+        let date = NaiveDate::from_ymd(2020, 11, 14);
+        let time = NaiveTime::from_hms(10, 20, 30).with_nanosecond(149000000).unwrap();
+        let datetime = Type::from_datetime(DateTime::from_utc(NaiveDateTime::new(date, time), Utc));
+
+        for t in [Type::from_int(-12), Type::from_str("hello"), Type::from_decimal(Decimal::from_f64(1.11).unwrap()), Type::from_bool(true), datetime] {
+            assert_eq!(Type::parse(&t.get_type(), &t.to_string()).unwrap(), t);
+        }
+
+        assert!(Type::parse("integer", "1.5").is_err());
+        assert!(Type::parse("boolean", "yes").is_err());
+        assert!(Type::parse("text", "hello").is_err());
+    }
+
     #[test]
     fn test_to() {
         let int = Type::from_int(0);
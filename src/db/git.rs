@@ -0,0 +1,533 @@
+//! Everything related to git
+//!
+//! When use_git is on, the database folder is a git repository and every
+//! write is a commit. The history of the tables is read back from it.
+//!
+//! With the git2 feature, init, commit and log are done in-process with libgit2.
+//! Otherwise (and for everything else), the git command is used.
+use std::path::Path;
+use std::process::{Command, Output};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::util::file;
+
+use super::db_error::DbError;
+
+#[cfg(feature = "git2")]
+mod in_process;
+
+#[cfg(feature = "git2")]
+pub(crate) use in_process::{commit, init, log};
+
+/**
+ * Who changed a line, and its fields, last (see Db::blame)
+ */
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Blame {
+    /**
+     * The commit that added the line
+     */
+    pub created: Commit,
+    /**
+     * The last commit that changed each field, in the order of the fields of the line
+     */
+    pub fields: Vec<(String, Commit)>
+}
+
+/// Patterns of the files git must not commit
+const IGNORED: &[&str] = &["*.lock", "*.tmp", "/.snapshots/"];
+
+/// Name of the merge driver in the git config and in .gitattributes
+const MERGE_DRIVER: &str = "rental_rod";
+
+/// Separates the fields of a commit in the output of git log
+#[cfg(not(feature = "git2"))]
+const FIELD_SEPARATOR: char = '\u{1f}';
+/// Separates the commits in the output of git log
+#[cfg(not(feature = "git2"))]
+const COMMIT_SEPARATOR: char = '\u{1e}';
+
+/**
+ * A commit of the database (see Db::history)
+ */
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Commit {
+    /**
+     * The full hash of the commit
+     */
+    pub id: String,
+    pub message: String,
+    pub author: String,
+    pub email: String,
+    pub timestamp: DateTime<Utc>
+}
+
+/**
+ * Options applied to every commit (see Db::set_git_author_name, etc.)
+ */
+#[derive(Clone)]
+#[derive(Default)]
+pub(crate) struct CommitOptions {
+    /**
+     * Empty to use the identity configured in git
+     */
+    pub author_name: String,
+    /**
+     * Empty to use the identity configured in git
+     */
+    pub author_email: String,
+    /**
+     * Extra flags given to git commit (e.g. -S to sign the commits)
+     */
+    pub flags: Vec<String>
+}
+
+/**
+ * Create the repository if it does not exist
+ */
+#[cfg(not(feature = "git2"))]
+pub(crate) fn init(path: &str) -> Result<(), DbError> {
+    if !exists(path) {
+        run(Command::new("git").arg("init").arg(path))?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn exists(path: &str) -> bool {
+    Path::new(path).join(".git").exists()
+}
+
+/**
+ * Write the .gitignore of the database if it does not exist
+ */
+pub(crate) fn ignore(path: &str) -> Result<(), DbError> {
+    let path = Path::new(path).join(".gitignore");
+    let mut content = std::fs::read_to_string(&path).unwrap_or_default();
+
+    // Lock files, temporary files and snapshot folders are not part of the data
+    let mut changed = false;
+    for pattern in IGNORED {
+        if !content.lines().any(|l| l == *pattern) {
+            content.push_str(pattern);
+            content.push('\n');
+            changed = true;
+        }
+    }
+
+    if changed {
+        std::fs::write(path, content)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Commit everything in the database folder
+ *
+ * It does nothing when nothing changed since the last commit
+ */
+#[cfg(not(feature = "git2"))]
+pub(crate) fn commit(path: &str, msg: &str, options: &CommitOptions) -> Result<(), DbError> {
+    commit_with_cli(path, msg, options)
+}
+
+fn commit_with_cli(path: &str, msg: &str, options: &CommitOptions) -> Result<(), DbError> {
+    run(git(path).arg("add").arg("."))?;
+    if !has_staged_changes(path)? {
+        return Ok(());
+    }
+
+    let mut command = git(path);
+    if !options.author_name.is_empty() {
+        command.arg("-c").arg(String::from("user.name=") + &options.author_name);
+    }
+    if !options.author_email.is_empty() {
+        command.arg("-c").arg(String::from("user.email=") + &options.author_email);
+    }
+    run(command.arg("commit").args(&options.flags).arg("-m").arg(msg))?;
+
+    Ok(())
+}
+
+fn has_staged_changes(path: &str) -> Result<bool, DbError> {
+    // There is nothing to compare to before the first commit
+    let head = git(path).arg("rev-parse").arg("--verify").arg("--quiet").arg("HEAD").output()?;
+    if !head.status.success() {
+        return Ok(true);
+    }
+
+    // A merge is always committed, even if it does not change anything
+    if is_merging(path)? {
+        return Ok(true);
+    }
+
+    // Exits with 1 when there are differences
+    let output = git(path).arg("diff").arg("--cached").arg("--quiet").output()?;
+    match output.status.code() {
+        Some(0) => Ok(false),
+        Some(1) => Ok(true),
+        _ => Err(git_error(&output))
+    }
+}
+
+/**
+ * The commits that touched one of the files, the most recent first
+ */
+#[cfg(not(feature = "git2"))]
+pub(crate) fn log(path: &str, files: &[&str]) -> Result<Vec<Commit>, DbError> {
+    let format = format!("--format=%H{0}%an{0}%ae{0}%at{0}%B{1}", FIELD_SEPARATOR, COMMIT_SEPARATOR);
+    let output = run(git(path).arg("log").arg(format).arg("--").args(files))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    let mut commits = Vec::new();
+    for raw in text.split(COMMIT_SEPARATOR) {
+        let raw = raw.trim_start_matches('\n');
+        if raw.is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = raw.splitn(5, FIELD_SEPARATOR).collect();
+        if fields.len() != 5 {
+            return Err(DbError::Custom(String::from("Unexpected output from git log")));
+        }
+
+        let timestamp = match fields[3].parse::<i64>() {
+            Ok(t) => t,
+            Err(_) => return Err(DbError::Custom(String::from("Invalid commit date [") + fields[3] + "]"))
+        };
+
+        commits.push(Commit {
+            id: String::from(fields[0]),
+            author: String::from(fields[1]),
+            email: String::from(fields[2]),
+            timestamp: to_datetime(timestamp)?,
+            message: String::from(fields[4].trim_end())
+        });
+    }
+
+    Ok(commits)
+}
+
+/**
+ * Resolve a revision (hash, branch, tag, HEAD~1, etc.) to the hash of a commit
+ */
+pub(crate) fn resolve(path: &str, rev: &str) -> Result<String, DbError> {
+    let output = git(path)
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg(String::from(rev) + "^{commit}")
+        .output()?;
+
+    if !output.status.success() {
+        return Err(DbError::Custom(String::from("Unknown revision [") + rev + "]"));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/**
+ * The content of the file at the commit (None if the file did not exist)
+ */
+pub(crate) fn show(path: &str, commit: &str, file: &str) -> Result<Option<Vec<u8>>, DbError> {
+    let output = git(path).arg("show").arg(String::from(commit) + ":" + file).output()?;
+
+    if output.status.success() {
+        Ok(Some(output.stdout))
+    }
+    else {
+        Ok(None)
+    }
+}
+
+/**
+ * The files at the root of the repository at the commit
+ */
+pub(crate) fn files(path: &str, commit: &str) -> Result<Vec<String>, DbError> {
+    let output = run(git(path).arg("ls-tree").arg("--name-only").arg(commit))?;
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect())
+}
+
+/**
+ * The commits that touched one of the files with their parents, the oldest first
+ *
+ * The parents are rewritten to the previous commits that touched the files, so a
+ * merge has the last commit of each branch as parents.
+ */
+pub(crate) fn parents(path: &str, files: &[&str]) -> Result<Vec<(String, Vec<String>)>, DbError> {
+    let output = run(git(path).arg("rev-list").arg("--parents").arg("--topo-order").arg("--reverse").arg("HEAD").arg("--").args(files))?;
+
+    let mut commits = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mut ids = line.split_whitespace().map(String::from);
+        if let Some(id) = ids.next() {
+            commits.push((id, ids.collect()));
+        }
+    }
+
+    Ok(commits)
+}
+
+/**
+ * Create a branch on the current commit
+ */
+pub(crate) fn create_branch(path: &str, name: &str) -> Result<(), DbError> {
+    run(git(path).arg("branch").arg("--").arg(name))?;
+
+    Ok(())
+}
+
+/**
+ * Switch to the branch, the files are replaced by the ones of the branch
+ */
+pub(crate) fn checkout(path: &str, name: &str) -> Result<(), DbError> {
+    run(git(path).arg("checkout").arg("--quiet").arg(name).arg("--"))?;
+
+    Ok(())
+}
+
+/**
+ * The name of the current branch
+ */
+pub(crate) fn current_branch(path: &str) -> Result<String, DbError> {
+    let output = run(git(path).arg("rev-parse").arg("--abbrev-ref").arg("HEAD"))?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/**
+ * The best common ancestor of two commits (None if they have no common history)
+ */
+pub(crate) fn merge_base(path: &str, a: &str, b: &str) -> Result<Option<String>, DbError> {
+    let output = git(path).arg("merge-base").arg(a).arg(b).output()?;
+
+    match output.status.code() {
+        Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())),
+        Some(1) => Ok(None),
+        _ => Err(git_error(&output))
+    }
+}
+
+/**
+ * Move the current branch to the commit (it must be a descendant of the current commit)
+ */
+pub(crate) fn fast_forward(path: &str, commit: &str) -> Result<(), DbError> {
+    run(git(path).arg("merge").arg("--quiet").arg("--ff-only").arg(commit))?;
+
+    Ok(())
+}
+
+/**
+ * Start a merge with the commit, without changing any file
+ *
+ * The merged files are written by the caller, and the next commit completes the merge
+ */
+pub(crate) fn start_merge(path: &str, commit: &str) -> Result<(), DbError> {
+    run(git(path)
+        .arg("merge")
+        .arg("--quiet")
+        .arg("--strategy=ours")
+        .arg("--no-commit")
+        .arg("--no-ff")
+        .arg(commit))?;
+
+    Ok(())
+}
+
+/**
+ * Abort the merge started by start_merge
+ *
+ * The files written since the merge started are not restored (see restore_files)
+ */
+pub(crate) fn abort_merge(path: &str) -> Result<(), DbError> {
+    run(git(path).arg("merge").arg("--abort"))?;
+
+    Ok(())
+}
+
+/**
+ * Restore the files as they are in the commit, the files that are not in the commit are removed
+ */
+pub(crate) fn restore_files(path: &str, commit: &str, names: &[String]) -> Result<(), DbError> {
+    let in_commit = files(path, commit)?;
+    let (restored, removed): (Vec<&String>, Vec<&String>) = names.iter().partition(|name| in_commit.contains(name));
+
+    for name in removed {
+        file::remove_file(&Path::new(path).join(name).to_string_lossy())?;
+    }
+    if !restored.is_empty() {
+        run(git(path).arg("checkout").arg(commit).arg("--").args(restored))?;
+    }
+
+    Ok(())
+}
+
+/**
+ * The base and the other commit of the operation in progress (merge, cherry-pick, revert or rebase)
+ *
+ * It is used by the merge driver, git only gives it the files. None when no
+ * operation is in progress (or when the other commit has no parent).
+ *
+ * While `git merge` runs the drivers, MERGE_HEAD is not written yet: git
+ * exports the merged commit as a GITHEAD_<commit> variable instead.
+ */
+pub(crate) fn merged_commits(path: &str) -> Result<Option<(String, String)>, DbError> {
+    let heads: Vec<String> = std::env::vars()
+        .filter_map(|(name, _)| name.strip_prefix("GITHEAD_").map(String::from))
+        .collect();
+    if let [theirs] = heads.as_slice() {
+        if let Ok(theirs) = resolve(path, theirs) {
+            return Ok(merge_base(path, "HEAD", &theirs)?.map(|base| (base, theirs)));
+        }
+    }
+
+    if let Ok(theirs) = resolve(path, "MERGE_HEAD") {
+        return Ok(merge_base(path, "HEAD", &theirs)?.map(|base| (base, theirs)));
+    }
+
+    for name in ["CHERRY_PICK_HEAD", "REBASE_HEAD"] {
+        if let Ok(theirs) = resolve(path, name) {
+            return Ok(resolve(path, &(theirs.clone() + "^")).ok().map(|base| (base, theirs)));
+        }
+    }
+
+    // A revert applies the changes from the commit to its parent
+    if let Ok(base) = resolve(path, "REVERT_HEAD") {
+        return Ok(resolve(path, &(base.clone() + "^")).ok().map(|theirs| (base, theirs)));
+    }
+
+    Ok(None)
+}
+
+/**
+ * Fetch the branch from the remote (a name or an url)
+ *
+ * Returns the fetched commit, or None if the remote does not have the branch
+ */
+pub(crate) fn fetch(path: &str, remote: &str, branch: &str) -> Result<Option<String>, DbError> {
+    let reference = String::from("refs/heads/") + branch;
+    let output = run(git(path).arg("ls-remote").arg("--heads").arg(remote).arg(&reference))?;
+    if output.stdout.is_empty() {
+        return Ok(None);
+    }
+
+    run(git(path).arg("fetch").arg("--quiet").arg(remote).arg(&reference))?;
+
+    Ok(Some(resolve(path, "FETCH_HEAD")?))
+}
+
+/**
+ * Push the current commit to the branch of the remote
+ */
+pub(crate) fn push(path: &str, remote: &str, branch: &str) -> Result<(), DbError> {
+    run(git(path).arg("push").arg("--quiet").arg(remote).arg(String::from("HEAD:refs/heads/") + branch))?;
+
+    Ok(())
+}
+
+/**
+ * Create an annotated tag on the current commit
+ */
+pub(crate) fn tag(path: &str, name: &str, msg: &str) -> Result<(), DbError> {
+    run(git(path).arg("tag").arg("--annotate").arg("--message").arg(msg).arg(name))?;
+
+    Ok(())
+}
+
+/**
+ * The tags starting with the prefix, without the prefix
+ */
+pub(crate) fn tags(path: &str, prefix: &str) -> Result<Vec<String>, DbError> {
+    let output = run(git(path).arg("tag").arg("--list").arg(String::from(prefix) + "*"))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    Ok(text.lines().filter_map(|t| t.strip_prefix(prefix)).map(String::from).collect())
+}
+
+/**
+ * The files changed since the last commit, including the new files (the ignored files are not listed)
+ */
+pub(crate) fn changed_files(path: &str) -> Result<Vec<String>, DbError> {
+    let output = run(git(path).arg("status").arg("--porcelain").arg("-z").arg("--untracked-files=all"))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    // Every entry is the status (2 characters), a space and the path
+    Ok(text.split_terminator('\0').filter_map(|entry| entry.get(3..)).map(String::from).collect())
+}
+
+fn is_merging(path: &str) -> Result<bool, DbError> {
+    let output = git(path).arg("rev-parse").arg("--verify").arg("--quiet").arg("MERGE_HEAD").output()?;
+
+    Ok(output.status.success())
+}
+
+/**
+ * Use the command as the merge driver of every file of the repository
+ *
+ * The command is called with the base, ours and theirs files, and the path of the file
+ * in the repository (see merge::merge_tracked_files)
+ */
+pub(crate) fn set_merge_driver(path: &str, command: &str) -> Result<(), DbError> {
+    let attributes = Path::new(path).join(".gitattributes");
+    let line = String::from("* merge=") + MERGE_DRIVER;
+    let mut content = std::fs::read_to_string(&attributes).unwrap_or_default();
+    if !content.lines().any(|l| l == line) {
+        content.push_str(&line);
+        content.push('\n');
+        std::fs::write(&attributes, content)?;
+    }
+
+    let key = String::from("merge.") + MERGE_DRIVER;
+    config(path, &(key.clone() + ".name"), "rental_rod table merge")?;
+    config(path, &(key + ".driver"), &(String::from(command) + " %O %A %B %P"))
+}
+
+fn config(path: &str, key: &str, value: &str) -> Result<(), DbError> {
+    run(git(path).arg("config").arg(key).arg(value))?;
+
+    Ok(())
+}
+
+/**
+ * Convert a unix timestamp (in seconds) to a DateTime
+ */
+fn to_datetime(timestamp: i64) -> Result<DateTime<Utc>, DbError> {
+    match Utc.timestamp_opt(timestamp, 0).single() {
+        Some(t) => Ok(t),
+        None => Err(DbError::Custom(String::from("Invalid commit date [") + &timestamp.to_string() + "]"))
+    }
+}
+
+fn git(path: &str) -> Command {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(path);
+
+    command
+}
+
+/**
+ * Run the command, it fails with DbError::Git if git exits with an error
+ */
+fn run(command: &mut Command) -> Result<Output, DbError> {
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(error) => return Err(DbError::Custom(error.to_string()))
+    };
+
+    if !output.status.success() {
+        return Err(git_error(&output));
+    }
+
+    Ok(output)
+}
+
+fn git_error(output: &Output) -> DbError {
+    DbError::Git {
+        code: output.status.code().unwrap_or(-1),
+        stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned()
+    }
+}
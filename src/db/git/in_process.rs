@@ -0,0 +1,126 @@
+//! The git operations done with libgit2 (git2 feature), instead of the git command
+use std::path::Path;
+
+use git2::{IndexAddOption, Oid, Repository, Signature, Sort, Tree};
+
+use crate::db::db_error::DbError;
+
+use super::{commit_with_cli, exists, to_datetime, Commit, CommitOptions};
+
+impl From<git2::Error> for DbError {
+    fn from(error: git2::Error) -> DbError {
+        DbError::Git { code: error.raw_code(), stderr: String::from(error.message()) }
+    }
+}
+
+/**
+ * Create the repository if it does not exist
+ */
+pub(crate) fn init(path: &str) -> Result<(), DbError> {
+    if !exists(path) {
+        Repository::init(path)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Commit everything in the database folder
+ *
+ * It does nothing when nothing changed since the last commit. The git command
+ * is used when there are extra flags, libgit2 does not know about them.
+ */
+pub(crate) fn commit(path: &str, msg: &str, options: &CommitOptions) -> Result<(), DbError> {
+    if !options.flags.is_empty() {
+        return commit_with_cli(path, msg, options);
+    }
+
+    let repo = Repository::open(path)?;
+
+    // Same as git add . (the deleted files are removed from the index)
+    let mut index = repo.index()?;
+    index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"].iter(), None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        // There is nothing to compare to before the first commit
+        Err(_) => None
+    };
+    // The merge started by start_merge, it is always committed
+    let merged = match repo.find_reference("MERGE_HEAD") {
+        Ok(reference) => Some(reference.peel_to_commit()?),
+        Err(_) => None
+    };
+    if let (Some(parent), None) = (&parent, &merged) {
+        if parent.tree_id() == tree.id() {
+            return Ok(());
+        }
+    }
+
+    let signature = signature(&repo, options)?;
+    let parents: Vec<&git2::Commit> = parent.iter().chain(merged.iter()).collect();
+    repo.commit(Some("HEAD"), &signature, &signature, msg, &tree, &parents)?;
+    repo.cleanup_state()?;
+
+    Ok(())
+}
+
+/**
+ * The commits that touched one of the files, the most recent first
+ */
+pub(crate) fn log(path: &str, files: &[&str]) -> Result<Vec<Commit>, DbError> {
+    let repo = Repository::open(path)?;
+    if repo.head().is_err() {
+        return Ok(Vec::new());
+    }
+
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+
+    let mut commits = Vec::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let ids = file_ids(&commit.tree()?, files);
+
+        // Like git log, a merge is only shown if it differs from all its parents
+        let mut touched = commit.parent_count() > 0 || ids.iter().any(Option::is_some);
+        for parent in commit.parents() {
+            touched = touched && file_ids(&parent.tree()?, files) != ids;
+        }
+
+        if touched {
+            let author = commit.author();
+            commits.push(Commit {
+                id: commit.id().to_string(),
+                message: String::from(commit.message().unwrap_or_default().trim_end()),
+                author: String::from(author.name().unwrap_or_default()),
+                email: String::from(author.email().unwrap_or_default()),
+                timestamp: to_datetime(author.when().seconds())?
+            });
+        }
+    }
+
+    Ok(commits)
+}
+
+fn signature(repo: &Repository, options: &CommitOptions) -> Result<Signature<'static>, DbError> {
+    let config = repo.config()?;
+    let name = match options.author_name.as_str() {
+        "" => config.get_string("user.name")?,
+        name => String::from(name)
+    };
+    let email = match options.author_email.as_str() {
+        "" => config.get_string("user.email")?,
+        email => String::from(email)
+    };
+
+    Ok(Signature::now(&name, &email)?)
+}
+
+fn file_ids(tree: &Tree, files: &[&str]) -> Vec<Option<Oid>> {
+    files.iter().map(|f| tree.get_path(Path::new(f)).ok().map(|e| e.id())).collect()
+}
@@ -15,6 +15,8 @@ use super::db_error::DbError;
 use super::field_type::Type;
 
 #[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone)]
 /**
  * Struct for a Line (a Table contains multiple line)
  * 
@@ -68,9 +70,10 @@ impl Line {
     /**
      * Add a new field with the specified Value/Type
      * 
-     * Will Throw an error if the field already exists
+     * Will Throw an error if the field already exists, or if the name is invalid (see Field::check_name)
      */
     pub fn add(&mut self, field_name: &str, value: Type) -> Result<(), DbError> {
+        Field::check_name(field_name)?;
         let fields = self.get_fields_name();
         if fields.contains(&field_name) {
             return Err(DbError::Custom(String::from("The field [") + field_name + "] already exists"));
@@ -204,6 +207,19 @@ fn test_get_field() {
     assert_eq!(line.get_fields_name().len(), 0);
 }
 
+#[test]
+fn test_add_invalid_name() {
+    let mut line = Line::new();
+
+    for name in ["", "_id", "first name", "[name", "name]", "\"name\"", "#name", "name:", "first\nname", "first\tname"] {
+        assert!(line.add(name, Type::from_str("Mike")).is_err(), "{:?}", name);
+    }
+    assert_eq!(line.get_fields().len(), 0);
+
+    line.add("_name-2.é", Type::from_str("Mike")).unwrap();
+    assert!(line.add("_name-2.é", Type::from_str("Mike")).is_err());
+}
+
 fn _init_line() -> Line {
     let mut line = Line::new();
     line.add("firstname", Type::from_str("Mike")).unwrap();
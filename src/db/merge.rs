@@ -0,0 +1,470 @@
+//! Everything related to merging two versions of a table
+//!
+//! It is a three-way merge: the lines are matched by id, and the changes made on
+//! each side since their common version (the base) are combined. When both sides
+//! changed the same line, the changes are combined field by field. There is only
+//! a conflict when both sides changed the same field differently (or when one side
+//! deleted a line the other side changed).
+//!
+//! The same merge is used as a git merge driver for the table files
+//! (see Db::set_merge_driver and the rental_rod_merge binary).
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::table_manager;
+use crate::util::file;
+
+use super::{db_error::DbError, field::Field, git, line::Line, table::Table, Db};
+
+/// Size of the conflict markers, the same as git
+const MARKER_SIZE: usize = 7;
+
+/**
+ * A line both sides changed in incompatible ways
+ */
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Conflict {
+    pub id: Uuid,
+    /**
+     * Our version of the line (None if we deleted it)
+     */
+    pub ours: Option<Line>,
+    /**
+     * Their version of the line (None if they deleted it)
+     */
+    pub theirs: Option<Line>,
+    /**
+     * The fields both sides changed differently (empty if one side deleted the line)
+     */
+    pub fields: Vec<String>
+}
+
+/**
+ * The result of a merge (see merge)
+ */
+#[derive(Debug)]
+pub struct MergeResult {
+    /**
+     * The merged lines, without the conflicts
+     */
+    pub lines: Vec<Line>,
+    pub conflicts: Vec<Conflict>
+}
+
+impl MergeResult {
+    /**
+     * True if there are no conflicts
+     */
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/**
+ * Merge our version and their version of a table, based on their common version
+ */
+pub fn merge(base: &Table, ours: &Table, theirs: &Table) -> MergeResult {
+    let base_lines = base.get_lines();
+    let our_lines = ours.get_lines();
+    let their_lines = theirs.get_lines();
+    let base_by_id = by_id(&base_lines);
+    let ours_by_id = by_id(&our_lines);
+    let theirs_by_id = by_id(&their_lines);
+
+    // Keep our order, then the lines only they know about
+    let mut ids: Vec<&Uuid> = our_lines.iter().map(|l| l.get_id()).collect();
+    for line in base_lines.iter().chain(their_lines.iter()) {
+        if !ours_by_id.contains_key(line.get_id()) && !ids.contains(&line.get_id()) {
+            ids.push(line.get_id());
+        }
+    }
+
+    let mut result = MergeResult { lines: Vec::new(), conflicts: Vec::new() };
+    for id in ids {
+        let base = base_by_id.get(id).copied();
+        let ours = ours_by_id.get(id).copied();
+        let theirs = theirs_by_id.get(id).copied();
+
+        let merged = if ours == theirs || theirs == base {
+            ours.cloned()
+        }
+        else if ours == base {
+            theirs.cloned()
+        }
+        else {
+            match (ours, theirs) {
+                (Some(o), Some(t)) => {
+                    match merge_line(base, o, t) {
+                        Ok(line) => Some(line),
+                        Err(fields) => {
+                            result.conflicts.push(Conflict { id: *id, ours: ours.cloned(), theirs: theirs.cloned(), fields });
+                            None
+                        }
+                    }
+                },
+                // One side deleted the line, the other one changed it
+                _ => {
+                    result.conflicts.push(Conflict { id: *id, ours: ours.cloned(), theirs: theirs.cloned(), fields: vec![] });
+                    None
+                }
+            }
+        };
+
+        if let Some(line) = merged {
+            result.lines.push(line);
+        }
+    }
+
+    result
+}
+
+/**
+ * Merge the table files given by git to a merge driver
+ *
+ * The result is written to `ours`. The conflicts are written between conflict
+ * markers, like git does. Returns true if there are no conflicts.
+ */
+pub fn merge_files(base: &str, ours: &str, theirs: &str) -> Result<bool, DbError> {
+    let (base_raw, ours_raw, theirs_raw) = (file::read(base)?, file::read(ours)?, file::read(theirs)?);
+    let result = merge(&to_table(base, &base_raw)?, &to_table(ours, &ours_raw)?, &to_table(theirs, &theirs_raw)?);
+
+    write_result(ours, &result, version(&base_raw, &ours_raw, &theirs_raw))
+}
+
+/**
+ * Merge a file of the repository given by git to the merge driver
+ *
+ * `path` is the path of the file in the repository. A table and its journal are
+ * merged as one table: the versions of the table, with their journal, are read from
+ * the commits being merged (see git::merged_commits). The table file gets the merged
+ * lines. The journal gets the entries that turn the table file kept by git into the
+ * merged lines (it is empty when the table file is merged too).
+ *
+ * When the commits cannot be found, a table is merged by merge_files, and a journal
+ * is a conflict (ours is kept). It must be run from the root of the repository.
+ */
+pub fn merge_tracked_files(base: &str, ours: &str, theirs: &str, path: &str) -> Result<bool, DbError> {
+    let repo = ".";
+    let tbl = match path.strip_prefix('.').and_then(|name| name.strip_suffix(".journal")) {
+        Some(tbl) if file::journal_path(tbl) == path => tbl,
+        _ => path
+    };
+    let is_journal = tbl != path;
+
+    let commits = match merged_commits(repo, path, [base, ours, theirs])? {
+        Some(commits) => commits,
+        None if is_journal => return Ok(false),
+        None => return merge_files(base, ours, theirs)
+    };
+
+    let mut tables = Vec::new();
+    let mut raws = Vec::new();
+    for commit in &commits {
+        tables.push(match Db::read_table_at(repo, tbl, commit)? {
+            Some(table) => table,
+            None => Table::new(tbl, vec![])?
+        });
+        raws.push(git::show(repo, commit, tbl)?.map(|raw| to_lines(&raw)));
+    }
+    let result = merge(&tables[0], &tables[1], &tables[2]);
+
+    let no_table = Vec::new();
+    let [base_raw, ours_raw, theirs_raw] = [0, 1, 2].map(|i| raws[i].as_ref().unwrap_or(&no_table));
+    if !is_journal {
+        return write_result(ours, &result, version(base_raw, ours_raw, theirs_raw));
+    }
+    if !result.is_clean() {
+        return Ok(false);
+    }
+
+    // Like git, the table file is only merged when both sides changed it
+    let kept = if raws[1] == raws[2] || raws[0] == raws[2] {
+        &raws[1]
+    }
+    else if raws[0] == raws[1] {
+        &raws[2]
+    }
+    else {
+        &None
+    };
+    let content = match kept {
+        Some(raw) => table_manager::format_journal(raw, &result.lines.iter().collect::<Vec<_>>())?,
+        None => Vec::new()
+    };
+
+    std::fs::write(ours, content.iter().map(|l| l.clone() + "\n").collect::<String>())?;
+
+    Ok(true)
+}
+
+/**
+ * The base, ours and theirs commits of the file being merged (None if they cannot be found)
+ *
+ * The file must have the content git gave to the merge driver in each of them
+ */
+fn merged_commits(repo: &str, path: &str, files: [&str; 3]) -> Result<Option<[String; 3]>, DbError> {
+    let (base, theirs) = match git::merged_commits(repo)? {
+        Some(commits) => commits,
+        None => return Ok(None)
+    };
+
+    let commits = [base, git::resolve(repo, "HEAD")?, theirs];
+    for (commit, file) in commits.iter().zip(files) {
+        if git::show(repo, commit, path)?.unwrap_or_default() != std::fs::read(file)? {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(commits))
+}
+
+/**
+ * Write the merged lines and the conflicts to `ours`, returns true if there are no conflicts
+ */
+fn write_result(ours: &str, result: &MergeResult, version: u32) -> Result<bool, DbError> {
+    let format_line = |line: &Line| table_manager::format_line(line, version);
+
+    let mut content = table_manager::format_content(&result.lines.iter().collect::<Vec<_>>(), version)?;
+    for conflict in &result.conflicts {
+        content.push("<".repeat(MARKER_SIZE) + " ours");
+        content.extend(conflict.ours.iter().map(format_line));
+        content.push("=".repeat(MARKER_SIZE));
+        content.extend(conflict.theirs.iter().map(format_line));
+        content.push(">".repeat(MARKER_SIZE) + " theirs");
+    }
+
+    std::fs::write(ours, content.join("\n") + "\n")?;
+
+    Ok(result.is_clean())
+}
+
+/**
+ * The format of the merged table
+ *
+ * The most recent format of both sides is kept, so a merge never downgrades a table
+ */
+fn version(base_raw: &[String], ours_raw: &[String], theirs_raw: &[String]) -> u32 {
+    [ours_raw, theirs_raw].iter()
+        .filter_map(|raw| table_manager::content_version(raw))
+        .max()
+        .or_else(|| table_manager::content_version(base_raw))
+        .unwrap_or(table_manager::DEFAULT_VERSION)
+}
+
+/**
+ * Split the content of a file in lines, like file::read
+ */
+fn to_lines(raw: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(raw).split_terminator('\n').map(String::from).collect()
+}
+
+/**
+ * Merge the fields of a line both sides changed
+ *
+ * Returns the names of the conflicting fields if the fields cannot be merged
+ */
+fn merge_line(base: Option<&Line>, ours: &Line, theirs: &Line) -> Result<Line, Vec<String>> {
+    let mut names: Vec<&str> = ours.get_fields_name();
+    for name in theirs.get_fields_name() {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    let mut fields: Vec<Field> = Vec::new();
+    let mut conflicts = Vec::new();
+    for name in names {
+        let b = base.and_then(|l| l.get(name));
+        let o = ours.get(name);
+        let t = theirs.get(name);
+
+        let merged = if o == t || t == b {
+            o
+        }
+        else if o == b {
+            t
+        }
+        else {
+            conflicts.push(String::from(name));
+            None
+        };
+
+        if let Some(field) = merged {
+            fields.push(field.clone());
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(Line::new_with_id(*ours.get_id(), fields))
+    }
+    else {
+        Err(conflicts)
+    }
+}
+
+fn by_id<'a>(lines: &[&'a Line]) -> HashMap<&'a Uuid, &'a Line> {
+    lines.iter().map(|l| (l.get_id(), *l)).collect()
+}
+
+fn to_table(path: &str, raw: &[String]) -> Result<Table, DbError> {
+    // The file does not exist on one side
+    let lines = if raw.is_empty() {
+        vec![]
+    } else {
+        table_manager::parse_content(raw)?
+    };
+
+    Table::new(path, lines)
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use crate::db::{field::Field, line::Line, table::Table};
+    use crate::util::file;
+
+    use super::*;
+
+    #[test]
+    fn test_merge_fields() {
+        let id = Uuid::new_v4();
+        let base = _table(vec![Line::new_with_id(id, vec![Field::new_str("name", "Mike"), Field::new_int("age", 30)])]);
+        let ours = _table(vec![Line::new_with_id(id, vec![Field::new_str("name", "Mikey"), Field::new_int("age", 30)])]);
+        let theirs = _table(vec![Line::new_with_id(id, vec![Field::new_str("name", "Mike"), Field::new_int("age", 31), Field::new_str("city", "Paris")])]);
+
+        let result = merge(&base, &ours, &theirs);
+        assert!(result.is_clean());
+        assert_eq!(result.lines, vec![Line::new_with_id(id, vec![Field::new_str("name", "Mikey"), Field::new_int("age", 31), Field::new_str("city", "Paris")])]);
+    }
+
+    #[test]
+    fn test_merge_lines() {
+        let kept = Uuid::new_v4();
+        let deleted = Uuid::new_v4();
+        let base = _table(vec![
+            Line::new_with_id(kept, vec![Field::new_str("name", "Mike")]),
+            Line::new_with_id(deleted, vec![Field::new_str("name", "Bob")]),
+        ]);
+
+        let ours_line = Line::new_with_id(Uuid::new_v4(), vec![Field::new_str("name", "Sean")]);
+        let ours = _table(vec![
+            Line::new_with_id(kept, vec![Field::new_str("name", "Mike")]),
+            Line::new_with_id(deleted, vec![Field::new_str("name", "Bob")]),
+            ours_line.clone(),
+        ]);
+
+        let theirs_line = Line::new_with_id(Uuid::new_v4(), vec![Field::new_str("name", "Simon")]);
+        let theirs = _table(vec![Line::new_with_id(kept, vec![Field::new_str("name", "Mike")]), theirs_line.clone()]);
+
+        let result = merge(&base, &ours, &theirs);
+        assert!(result.is_clean());
+        assert_eq!(result.lines, vec![Line::new_with_id(kept, vec![Field::new_str("name", "Mike")]), ours_line, theirs_line]);
+    }
+
+    #[test]
+    fn test_merge_conflicts() {
+        let changed = Uuid::new_v4();
+        let deleted = Uuid::new_v4();
+        let base = _table(vec![
+            Line::new_with_id(changed, vec![Field::new_str("name", "Mike"), Field::new_int("age", 30)]),
+            Line::new_with_id(deleted, vec![Field::new_str("name", "Bob")]),
+        ]);
+        let ours = _table(vec![
+            Line::new_with_id(changed, vec![Field::new_str("name", "Mikey"), Field::new_int("age", 31)]),
+            Line::new_with_id(deleted, vec![Field::new_str("name", "Bobby")]),
+        ]);
+        let theirs = _table(vec![Line::new_with_id(changed, vec![Field::new_str("name", "Michael"), Field::new_int("age", 31)])]);
+
+        let result = merge(&base, &ours, &theirs);
+        assert!(result.lines.is_empty());
+        assert_eq!(result.conflicts.len(), 2);
+        assert_eq!(result.conflicts[0].id, changed);
+        assert_eq!(result.conflicts[0].fields, vec!["name"]);
+        assert_eq!(result.conflicts[1].id, deleted);
+        assert!(result.conflicts[1].fields.is_empty());
+        assert!(result.conflicts[1].theirs.is_none());
+    }
+
+    #[test]
+    fn test_merge_files() {
+        let id = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let base = "/tmp/test_merge_files_base";
+        let ours = "/tmp/test_merge_files_ours";
+        let theirs = "/tmp/test_merge_files_theirs";
+
+        let line = |name: &str| table_manager::format_content(&[&Line::new_with_id(id, vec![Field::new_str("name", name)])], 1).unwrap();
+        let other_line = table_manager::format_content(&[&Line::new_with_id(other, vec![Field::new_int("age", 12)])], 1).unwrap();
+
+        std::fs::write(base, line("Mike").join("\n")).unwrap();
+        std::fs::write(ours, line("Mike").join("\n")).unwrap();
+        std::fs::write(theirs, [line("Sean"), other_line[1..].to_vec()].concat().join("\n")).unwrap();
+        assert!(merge_files(base, ours, theirs).unwrap());
+        let merged = table_manager::parse_content(&file::read(ours).unwrap()).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].get("name").unwrap().get().to_string(), "Sean");
+
+        // Both sides changed the name
+        std::fs::write(ours, line("Simon").join("\n")).unwrap();
+        assert!(!merge_files(base, ours, theirs).unwrap());
+        let merged = file::read(ours).unwrap();
+        assert_eq!(merged[0], "#v1.0#");
+        assert_eq!(merged[2], "<<<<<<< ours");
+        assert_eq!(merged[3], line("Simon")[1]);
+        assert_eq!(merged[4], "=======");
+        assert_eq!(merged[5], line("Sean")[1]);
+        assert_eq!(merged[6], ">>>>>>> theirs");
+
+        // The table was created on both sides
+        std::fs::write(base, "").unwrap();
+        std::fs::write(ours, line("Mike").join("\n")).unwrap();
+        assert!(!merge_files(base, ours, theirs).unwrap());
+
+        for path in [base, ours, theirs] {
+            file::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_merge_files_v2() {
+        let id = Uuid::new_v4();
+        let base = "/tmp/test_merge_files_v2_base";
+        let ours = "/tmp/test_merge_files_v2_ours";
+        let theirs = "/tmp/test_merge_files_v2_theirs";
+
+        let content = |name: &str, version: u32| table_manager::format_content(&[&Line::new_with_id(id, vec![Field::new_str("name", name)])], version).unwrap().join("\n");
+
+        // The most recent format is kept, on either side
+        std::fs::write(base, content("Mike", 1)).unwrap();
+        std::fs::write(ours, content("Mike", 2)).unwrap();
+        std::fs::write(theirs, content("Sean", 1)).unwrap();
+        assert!(merge_files(base, ours, theirs).unwrap());
+        assert_eq!(file::read(ours).unwrap().join("\n"), content("Sean", 2));
+
+        std::fs::write(ours, content("Mike", 1)).unwrap();
+        std::fs::write(theirs, content("Sean", 2)).unwrap();
+        assert!(merge_files(base, ours, theirs).unwrap());
+        assert_eq!(file::read(ours).unwrap().join("\n"), content("Sean", 2));
+        std::fs::write(theirs, content("Sean", 1)).unwrap();
+
+        // The conflicting lines do not depend on the header
+        std::fs::write(ours, content("Simon", 2)).unwrap();
+        assert!(!merge_files(base, ours, theirs).unwrap());
+        let merged = file::read(ours).unwrap();
+        assert_eq!(merged[0], "#v2.0#");
+        assert_eq!(merged[3], "<<<<<<< ours");
+        assert_eq!(merged[4], table_manager::format_line(&Line::new_with_id(id, vec![Field::new_str("name", "Simon")]), 2));
+        assert!(merged[4].ends_with("name:string:\"Simon\""));
+
+        for path in [base, ours, theirs] {
+            file::remove_file(path).unwrap();
+        }
+    }
+
+    fn _table(lines: Vec<Line>) -> Table {
+        Table::new("test", lines).unwrap()
+    }
+}
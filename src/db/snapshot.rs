@@ -0,0 +1,317 @@
+//! Everything related to snapshots
+//!
+//! A snapshot marks the state of the whole database under a name (see Db::snapshot).
+//! When the database uses git, a snapshot is a tag. Otherwise, the tables are
+//! copied to a folder of SNAPSHOT_DIR.
+//!
+//! # Example
+//! ```
+//! use rental_rod::db::{Db, line::Line};
+//!
+//! let db = Db::new("/tmp/doc_snapshot").unwrap();
+//! let mut orders = db.table("orders").unwrap();
+//! orders.insert(Line::new());
+//! db.write(&mut orders).unwrap();
+//!
+//! let name = String::from("close-") + &Line::new().get_id().to_string();
+//! db.snapshot(&name).unwrap();
+//! let snapshot = db.open_snapshot(&name).unwrap();
+//! assert_eq!(snapshot.table("orders").unwrap().get_lines().len(), orders.get_lines().len());
+//! ```
+use std::path::Path;
+
+use crate::table_manager::{self, Options};
+
+use super::{db_error::DbError, git, table::Table, Db};
+
+/// Folder of the snapshots, when git is not used
+pub(crate) const SNAPSHOT_DIR: &str = ".snapshots";
+
+/// Prefix of the tags of the snapshots, when git is used
+pub(crate) const TAG_PREFIX: &str = "snapshot/";
+
+/**
+ * A read-only view of the database, as it was when the snapshot was taken
+ */
+pub struct Snapshot<'a> {
+    db: &'a Db,
+    source: Source
+}
+
+enum Source {
+    /**
+     * The commit of the tag
+     */
+    Tag(String),
+    /**
+     * The folder of the copied tables
+     */
+    Dir(String)
+}
+
+impl<'a> Snapshot<'a> {
+    pub(crate) fn open(db: &'a Db, name: &str) -> Result<Snapshot<'a>, DbError> {
+        let tag = String::from(TAG_PREFIX) + name;
+        if git::exists(&db.path) && git::tags(&db.path, TAG_PREFIX)?.iter().any(|t| t == name) {
+            let commit = git::resolve(&db.path, &tag)?;
+            return Ok(Snapshot { db, source: Source::Tag(commit) });
+        }
+
+        let dir = dir_path(db, name);
+        if Path::new(&dir).is_dir() {
+            return Ok(Snapshot { db, source: Source::Dir(dir) });
+        }
+
+        Err(DbError::Custom(String::from("The snapshot [") + name + "] does not exist"))
+    }
+
+    /**
+     * Read the table, as it was when the snapshot was taken
+     *
+     * A table that did not exist is empty, like with Db::table
+     */
+    pub fn table(&self, tbl: &str) -> Result<Table, DbError> {
+        match &self.source {
+            Source::Tag(commit) => match self.db.table_at_commit(tbl, commit)? {
+                Some(table) => Ok(table),
+                None => Table::new(tbl, vec![])
+            },
+            Source::Dir(dir) => {
+                let manager = table_manager::get_table_manager(dir, tbl, &self.db.options)?;
+                manager.read()
+            }
+        }
+    }
+
+    /**
+     * Lists the tables of the snapshot (without the system tables)
+     */
+    pub fn tables(&self) -> Result<Vec<String>, DbError> {
+        let mut tables = match &self.source {
+            Source::Tag(commit) => git::files(&self.db.path, commit)?,
+            Source::Dir(dir) => {
+                let mut names = Vec::new();
+                for entry in std::fs::read_dir(dir)? {
+                    if let Some(name) = entry?.file_name().to_str() {
+                        names.push(String::from(name));
+                    }
+                }
+
+                names
+            }
+        };
+        tables.retain(|name| !name.starts_with("."));
+        tables.sort();
+
+        Ok(tables)
+    }
+}
+
+/**
+ * Mark the current state of the database (see Db::snapshot)
+ */
+pub(crate) fn create(db: &Db, name: &str) -> Result<(), DbError> {
+    check_name(name)?;
+    if list(db)?.iter().any(|n| n == name) {
+        return Err(DbError::Custom(String::from("The snapshot [") + name + "] already exists"));
+    }
+
+    if db.use_git {
+        // The tag is on HEAD, the changes that are not committed would be missing
+        let changed = git::changed_files(&db.path)?;
+        if !changed.is_empty() {
+            return Err(DbError::Uncommitted(changed));
+        }
+
+        let msg = String::from("Snapshot [") + name + "]";
+        return git::tag(&db.path, &(String::from(TAG_PREFIX) + name), &msg);
+    }
+
+    let mut tables = db.tables()?;
+    tables.push(String::from(".config"));
+    tables.sort();
+
+    // Nobody can write the tables until all of them are copied, always lock in the same order (see Transaction)
+    let mut managers = Vec::new();
+    let mut locks = Vec::new();
+    for tbl in &tables {
+        let manager = table_manager::get_table_manager(&db.path, tbl, &db.options)?;
+        locks.push(manager.lock()?);
+        managers.push(manager);
+    }
+
+    // Copy every table (with the system tables) through the table managers
+    let dir = dir_path(db, name);
+    std::fs::create_dir_all(&dir)?;
+    for (tbl, manager) in tables.iter().zip(managers.iter()) {
+        // Dropped before it was locked
+        if !Path::new(&db.path).join(tbl).exists() {
+            continue;
+        }
+
+        let mut table = manager.read_locked()?;
+        let mut copy = table_manager::get_table_manager(&dir, tbl, &Options::default())?;
+        copy.write(&mut table, true)?;
+    }
+    drop(locks);
+
+    Ok(())
+}
+
+/**
+ * The names of the snapshots (tags and folders), sorted
+ */
+pub(crate) fn list(db: &Db) -> Result<Vec<String>, DbError> {
+    let mut names = Vec::new();
+    if git::exists(&db.path) {
+        names = git::tags(&db.path, TAG_PREFIX)?;
+    }
+
+    let dir = Path::new(&db.path).join(SNAPSHOT_DIR);
+    if dir.is_dir() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if let Some(name) = entry.file_name().to_str() {
+                if entry.path().is_dir() && !names.iter().any(|n| n == name) {
+                    names.push(String::from(name));
+                }
+            }
+        }
+    }
+    names.sort();
+
+    Ok(names)
+}
+
+fn check_name(name: &str) -> Result<(), DbError> {
+    let valid = !name.is_empty()
+        && !name.starts_with(['.', '-'])
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+
+    if !valid {
+        return Err(DbError::Custom(String::from("Invalid snapshot name [") + name + "]"));
+    }
+
+    Ok(())
+}
+
+fn dir_path(db: &Db, name: &str) -> String {
+    Path::new(&db.path).join(SNAPSHOT_DIR).join(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::db::{Db, db_error::DbError, field_type::Type, line::Line};
+    use crate::table_manager::{self, Options};
+
+    #[test]
+    fn test_snapshot_with_git() {
+        let mut db = _init_db("/tmp/test_snapshot_git");
+        db.set_use_git(true, None).unwrap();
+        _snapshot(&db);
+
+        // It is a tag, nothing is copied
+        assert!(!Path::new("/tmp/test_snapshot_git/.snapshots").exists());
+    }
+
+    #[test]
+    fn test_snapshot_uncommitted() {
+        let mut db = _init_db("/tmp/test_snapshot_uncommitted");
+        db.set_use_git(true, None).unwrap();
+        let mut orders = db.table("orders").unwrap();
+        orders.insert(_new_line());
+        db.write(&mut orders).unwrap();
+
+        // A line is written without being committed
+        let mut manager = table_manager::get_table_manager(&db.path, "orders", &Options::default()).unwrap();
+        let mut table = manager.read().unwrap();
+        table.insert(_new_line());
+        manager.write(&mut table, false).unwrap();
+
+        assert!(matches!(db.snapshot("2026-01"), Err(DbError::Uncommitted(files)) if files == vec!["orders"]));
+        assert!(db.snapshots().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_without_git() {
+        let db = _init_db("/tmp/test_snapshot_dir");
+        _snapshot(&db);
+
+        assert!(Path::new("/tmp/test_snapshot_dir/.snapshots/2026-01").is_dir());
+        assert_eq!(db.tables().unwrap(), vec!["orders"]);
+    }
+
+    #[test]
+    fn test_snapshot_locks_tables() {
+        let mut db = _init_db("/tmp/test_snapshot_locks");
+        for tbl in ["inventory", "orders"] {
+            let mut table = db.table(tbl).unwrap();
+            table.insert(_new_line());
+            db.write(&mut table).unwrap();
+        }
+
+        // The orders are being written: nothing is copied, not even the inventory
+        db.set_lock_timeout(std::time::Duration::from_millis(20));
+        let manager = table_manager::get_table_manager(&db.path, "orders", &Options::default()).unwrap();
+        let lock = manager.lock().unwrap();
+        assert!(db.snapshot("2026-01").is_err());
+        assert!(!Path::new("/tmp/test_snapshot_locks/.snapshots/2026-01").exists());
+
+        drop(lock);
+        db.snapshot("2026-01").unwrap();
+        assert_eq!(db.open_snapshot("2026-01").unwrap().tables().unwrap(), vec!["inventory", "orders"]);
+    }
+
+    #[test]
+    fn test_invalid_name() {
+        let db = _init_db("/tmp/test_snapshot_name");
+
+        for name in ["", ".hidden", "a/b", "a b", "a..b", "a.lock", "-a"] {
+            assert!(db.snapshot(name).is_err(), "{}", name);
+        }
+        assert!(db.open_snapshot("unknown").is_err());
+    }
+
+    fn _snapshot(db: &Db) {
+        let mut orders = db.table("orders").unwrap();
+        orders.insert(_new_line());
+        db.write(&mut orders).unwrap();
+
+        db.snapshot("2026-01").unwrap();
+        assert!(db.snapshot("2026-01").is_err());
+
+        orders.insert(_new_line());
+        db.write(&mut orders).unwrap();
+        db.snapshot("2026-02").unwrap();
+
+        orders.insert(_new_line());
+        db.write(&mut orders).unwrap();
+        assert_eq!(db.snapshots().unwrap(), vec!["2026-01", "2026-02"]);
+
+        let snapshot = db.open_snapshot("2026-01").unwrap();
+        assert_eq!(snapshot.tables().unwrap(), vec!["orders"]);
+        assert_eq!(snapshot.table("orders").unwrap().get_lines().len(), 1);
+        assert_eq!(snapshot.table("unknown").unwrap().get_lines().len(), 0);
+        assert_eq!(db.open_snapshot("2026-02").unwrap().table("orders").unwrap().get_lines().len(), 2);
+        assert_eq!(db.table("orders").unwrap().get_lines().len(), 3);
+    }
+
+    fn _init_db(p: &str) -> Db {
+        if Path::new(p).exists() {
+            std::fs::remove_dir_all(p).unwrap();
+        }
+
+        Db::new(p).unwrap()
+    }
+
+    fn _new_line() -> Line {
+        let mut line = Line::new();
+        line.add("col1", Type::from_str("123")).unwrap();
+
+        line
+    }
+}
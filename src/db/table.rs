@@ -1,7 +1,12 @@
 //! Everythin related to a table
+use std::collections::HashMap;
+
 use super::line::Line;
 use super::field::Field;
 use super::db_error::DbError;
+use super::diff;
+#[cfg(test)]
+use super::field_type::Type;
 use uuid::Uuid;
 
 /**
@@ -11,7 +16,30 @@ use uuid::Uuid;
  */
 pub struct Table {
     name: String,
-    lines: Vec<Line>
+    lines: Vec<Line>,
+    original: Vec<Line>,
+    version: Option<String>
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Default)]
+/**
+ * The ids of the lines that changed since the table was read (see Table::changes)
+ */
+pub struct Changes {
+    pub inserted: Vec<Uuid>,
+    pub modified: Vec<Uuid>,
+    pub deleted: Vec<Uuid>
+}
+
+impl Changes {
+    /**
+     * True if nothing changed
+     */
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.modified.is_empty() && self.deleted.is_empty()
+    }
 }
 
 impl Table {
@@ -26,7 +54,9 @@ impl Table {
 
         Ok (Table { 
             name: String::from(name), 
-            lines: lines 
+            lines: lines,
+            original: Vec::new(),
+            version: None
         })
     }
 
@@ -54,6 +84,96 @@ impl Table {
         &self.name
     }
 
+    /**
+     * Return the version of the file the table was read from
+     * 
+     * It is None if the table did not exist on disk
+     */
+    pub fn get_version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub(crate) fn set_version(&mut self, version: Option<String>) {
+        self.version = version;
+    }
+
+    /**
+     * Return the lines inserted, modified and deleted since the table was read (or written)
+     * 
+     * All the lines of a table that was not read from the database are inserted lines
+     */
+    pub fn changes(&self) -> Changes {
+        let original: HashMap<&Uuid, &Line> = self.original.iter().map(|l| (l.get_id(), l)).collect();
+        let current: HashMap<&Uuid, &Line> = self.lines.iter().map(|l| (l.get_id(), l)).collect();
+
+        let mut changes = Changes::default();
+        for line in &self.lines {
+            match original.get(line.get_id()) {
+                None => changes.inserted.push(*line.get_id()),
+                Some(o) => {
+                    if *o != line {
+                        changes.modified.push(*line.get_id());
+                    }
+                }
+            }
+        }
+
+        for line in &self.original {
+            if !current.contains_key(line.get_id()) {
+                changes.deleted.push(*line.get_id());
+            }
+        }
+
+        changes
+    }
+
+    /**
+     * Describe the changes, e.g. "+2 lines, -1 line, 3 fields changed on 84e4…"
+     * 
+     * Returns an empty string when nothing changed
+     */
+    pub fn describe_changes(&self) -> String {
+        let changes = self.changes();
+
+        let mut parts = Vec::new();
+        if !changes.inserted.is_empty() {
+            parts.push(format!("+{}", plural(changes.inserted.len(), "line")));
+        }
+        if !changes.deleted.is_empty() {
+            parts.push(format!("-{}", plural(changes.deleted.len(), "line")));
+        }
+        if !changes.modified.is_empty() {
+            let mut fields = 0;
+            for id in &changes.modified {
+                if let (Some(before), Some(after)) = (self.get_original(id), self.get(id)) {
+                    fields += diff::diff_lines(before, after).len();
+                }
+            }
+
+            let on = match changes.modified.as_slice() {
+                [id] => id.to_string()[..4].to_owned() + "…",
+                modified => plural(modified.len(), "line")
+            };
+            parts.push(format!("{} changed on {}", plural(fields, "field"), on));
+        }
+
+        parts.join(", ")
+    }
+
+    /**
+     * Return the line as it was when the table was read (or written)
+     */
+    pub fn get_original(&self, id: &Uuid) -> Option<&Line> {
+        self.original.iter().find(|l| l.get_id() == id)
+    }
+
+    /**
+     * Forget the changes, the current lines are now the original lines
+     */
+    pub(crate) fn reset_changes(&mut self) {
+        self.original = self.lines.clone();
+    }
+
     /**
      * Return all the lines
      */
@@ -119,6 +239,13 @@ impl Table {
         list
     }
 
+    /**
+     * Return a line based on it's id
+     */
+    pub fn get(&self, id: &Uuid) -> Option<&Line> {
+        self.lines.iter().find(|l| l.get_id() == id)
+    }
+
     /**
      * Return a mutable line based on it's id
      */
@@ -275,6 +402,84 @@ fn test_find_where() {
     assert_eq!(lines.len(), table.lines.len());
 }
 
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    }
+    else {
+        format!("{} {}s", count, word)
+    }
+}
+
+#[test]
+fn test_changes() {
+    let mut table = _init_basic_table();
+    let changes = table.changes();
+    assert_eq!(changes.inserted.len(), 6);
+    assert!(changes.modified.is_empty());
+    assert!(changes.deleted.is_empty());
+
+    table.reset_changes();
+    assert!(table.changes().is_empty());
+
+    // Modify a line through find_mut
+    for line in table.find_mut(|l| l.has_with("firstname", "Mike")) {
+        line.get_mut("favorite_number").unwrap().set(Type::from_int(7));
+    }
+
+    // Delete a line
+    let deleted = Uuid::parse_str("9f77958d-378a-4aab-9763-c815cd74f2bd").unwrap();
+    table.delete(&deleted);
+
+    // Insert a line
+    let line = Line::new();
+    let inserted = *line.get_id();
+    table.insert(line);
+
+    // Modify a line and put it back the way it was
+    let id = Uuid::parse_str("187de314-404d-439b-8a68-58122ea12261").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("firstname").unwrap().set(Type::from_str("John"));
+    table.find_by_id(&id).unwrap().get_mut("firstname").unwrap().set(Type::from_str("Sean"));
+
+    let changes = table.changes();
+    assert_eq!(changes.inserted, vec![inserted]);
+    assert_eq!(changes.modified, vec![Uuid::parse_str("84e4eedf-a383-457e-aa73-d26c646762ba").unwrap()]);
+    assert_eq!(changes.deleted, vec![deleted]);
+
+    let modified = &changes.modified[0];
+    assert_eq!(table.get_original(modified).unwrap().get("favorite_number").unwrap().get().to_string(), "1245");
+    assert_eq!(table.get(modified).unwrap().get("favorite_number").unwrap().get().to_string(), "7");
+    assert!(table.get_original(&inserted).is_none());
+    assert!(table.get(&deleted).is_none());
+    assert!(table.get_original(&deleted).is_some());
+
+    table.reset_changes();
+    assert!(table.changes().is_empty());
+}
+
+#[test]
+fn test_describe_changes() {
+    let mut table = _init_basic_table();
+    assert_eq!(table.describe_changes(), "+6 lines");
+
+    table.reset_changes();
+    assert_eq!(table.describe_changes(), "");
+
+    let id = Uuid::parse_str("84e4eedf-a383-457e-aa73-d26c646762ba").unwrap();
+    let line = table.find_by_id(&id).unwrap();
+    line.get_mut("favorite_number").unwrap().set(Type::from_int(7));
+    line.remove("lastname");
+    line.add("nickname", Type::from_str("Mikey")).unwrap();
+    table.delete(&Uuid::parse_str("9f77958d-378a-4aab-9763-c815cd74f2bd").unwrap());
+    table.insert(Line::new());
+    table.insert(Line::new());
+    assert_eq!(table.describe_changes(), "+2 lines, -1 line, 3 fields changed on 84e4…");
+
+    let id = Uuid::parse_str("187de314-404d-439b-8a68-58122ea12261").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("firstname").unwrap().set(Type::from_str("John"));
+    assert_eq!(table.describe_changes(), "+2 lines, -1 line, 4 fields changed on 2 lines");
+}
+
 
 fn _init_basic_table() -> Table {
     let mut table = Table::new("test", vec![]).unwrap();
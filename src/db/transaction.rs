@@ -0,0 +1,410 @@
+//! Everything related to transactions
+//!
+//! A transaction writes multiple tables at once. Either all the tables
+//! are written, or none of them.
+//!
+//! If the process dies while the tables are being replaced, the transaction
+//! is completed the next time the database is opened (see Db::new).
+//!
+//! # Example
+//! ```
+//! use rental_rod::db::{Db, line::Line};
+//!
+//! let db = Db::new("/tmp/doc_transaction").unwrap();
+//! let mut orders = db.table("orders").unwrap();
+//! let mut inventory = db.table("inventory").unwrap();
+//! orders.insert(Line::new());
+//! inventory.insert(Line::new());
+//!
+//! let mut tx = db.transaction();
+//! tx.write(&mut orders).unwrap();
+//! tx.write(&mut inventory).unwrap();
+//! tx.commit().unwrap();
+//! ```
+use std::path::Path;
+
+use crate::table_manager::{self, TableManager};
+use crate::util::{file, lock::Lock};
+
+use super::{db_error::DbError, table::Table, Db};
+
+/// Name of the file listing the tables of the transaction being committed
+const JOURNAL: &str = ".transaction";
+const JOURNAL_VERSION: &str = "#transaction#";
+/// Separates the tables to replace from the tables to drop in the journal
+const JOURNAL_DROPPED: &str = "#dropped#";
+
+/**
+ * A group of tables to write together (see Db::transaction)
+ *
+ * Nothing is written until commit is called. Dropping the transaction
+ * without calling commit discards it.
+ */
+pub struct Transaction<'a> {
+    db: &'a Db,
+    tables: Vec<(&'a mut Table, bool)>,
+    dropped: Vec<String>
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(db: &'a Db) -> Transaction<'a> {
+        Transaction { db, tables: Vec::new(), dropped: Vec::new() }
+    }
+
+    /**
+     * Add the table to the transaction
+     *
+     * Like Db::write, the commit fails with DbError::Conflict if the table was
+     * modified on disk since it was read
+     */
+    pub fn write(&mut self, table: &'a mut Table) -> Result<(), DbError> {
+        self.add(table, false)
+    }
+
+    /**
+     * Add the table to the transaction, it will overwrite the changes made by the others
+     */
+    pub fn force_write(&mut self, table: &'a mut Table) -> Result<(), DbError> {
+        self.add(table, true)
+    }
+
+    /**
+     * Drop the table with the other tables of the transaction
+     */
+    pub(crate) fn drop_table(&mut self, tbl: &str) -> Result<(), DbError> {
+        self.check_not_added(tbl)?;
+        self.dropped.push(String::from(tbl));
+
+        Ok(())
+    }
+
+    fn add(&mut self, table: &'a mut Table, force: bool) -> Result<(), DbError> {
+        self.check_not_added(table.get_name())?;
+        self.tables.push((table, force));
+
+        Ok(())
+    }
+
+    fn check_not_added(&self, tbl: &str) -> Result<(), DbError> {
+        if self.tables.iter().any(|(t, _)| t.get_name() == tbl) || self.dropped.iter().any(|t| t == tbl) {
+            let msg = String::from("The table [") + tbl + "] is already part of the transaction";
+            return Err(DbError::Custom(msg));
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Discard the transaction, nothing is written
+     */
+    pub fn rollback(self) {}
+
+    /**
+     * Write all the tables of the transaction
+     *
+     * When git is used, the whole transaction is a single commit
+     */
+    pub fn commit(self) -> Result<(), DbError> {
+        self.commit_as(None)
+    }
+
+    /**
+     * Write all the tables of the transaction, with this commit message instead of the list of the changes
+     */
+    pub(crate) fn commit_with_message(self, msg: &str) -> Result<(), DbError> {
+        self.commit_as(Some(msg))
+    }
+
+    fn commit_as(mut self, message: Option<&str>) -> Result<(), DbError> {
+        let journal = journal_path(self.db);
+        let timeout = self.db.options.lock_timeout;
+        let journal_lock = Lock::exclusive(&journal, timeout)?;
+
+        self.tables.sort_by(|(a, _), (b, _)| a.get_name().cmp(b.get_name()));
+        self.dropped.sort();
+        // The changes are reset by the commit
+        let changes: Vec<String> = self.tables.iter().map(|(t, _)| t.describe_changes()).collect();
+
+        let mut managers = Vec::new();
+        for (table, _) in &self.tables {
+            managers.push(table_manager::get_table_manager(&self.db.path, table.get_name(), &self.db.options)?);
+        }
+        let mut dropped = Vec::new();
+        for tbl in &self.dropped {
+            dropped.push(table_manager::get_table_manager(&self.db.path, tbl, &self.db.options)?);
+        }
+
+        // Always lock in the same order
+        let mut ordered: Vec<(&str, &Box<dyn TableManager>)> = self.tables.iter().map(|(t, _)| t.get_name())
+            .chain(self.dropped.iter().map(String::as_str))
+            .zip(managers.iter().chain(dropped.iter()))
+            .collect();
+        ordered.sort_by_key(|(name, _)| *name);
+        let mut locks = Vec::new();
+        for (_, manager) in ordered {
+            locks.push(manager.lock()?);
+        }
+
+        if let Err(error) = self.prepare(&managers, &journal) {
+            for manager in &managers {
+                let _ = manager.rollback();
+            }
+
+            return Err(error);
+        }
+
+        // From here, the transaction is recovered by Db::new if something goes wrong
+        let mut msg = String::from("Transaction\n");
+        for ((manager, (table, _)), changes) in managers.iter().zip(self.tables.iter_mut()).zip(changes.iter()) {
+            let created = manager.commit(table)?;
+            msg.push('\n');
+            msg.push_str(&Db::write_message(table, created, changes));
+        }
+        for (manager, tbl) in dropped.iter().zip(self.dropped.iter()) {
+            manager.remove()?;
+            msg.push_str(&(String::from("\nDrop table [") + tbl + "]"));
+        }
+
+        file::remove_file(&journal)?;
+        drop(locks);
+        drop(journal_lock);
+
+        self.db.git_commit(message.unwrap_or(&msg))
+    }
+
+    fn prepare(&self, managers: &[Box<dyn TableManager>], journal: &str) -> Result<(), DbError> {
+        let mut names = Vec::new();
+        for (manager, (table, force)) in managers.iter().zip(self.tables.iter()) {
+            manager.stage(table, *force)?;
+            names.push(String::from(table.get_name()));
+        }
+        if !self.dropped.is_empty() {
+            names.push(String::from(JOURNAL_DROPPED));
+            names.extend(self.dropped.iter().cloned());
+        }
+
+        file::write_tmp(journal, JOURNAL_VERSION, &names)?;
+        file::commit_tmp(journal)?;
+
+        Ok(())
+    }
+}
+
+/**
+ * Complete a transaction interrupted while the tables were being replaced
+ *
+ * Returns true if a transaction was recovered
+ */
+pub(crate) fn recover(db: &Db) -> Result<bool, DbError> {
+    let journal = journal_path(db);
+    let timeout = db.options.lock_timeout;
+    let _lock = Lock::exclusive(&journal, timeout)?;
+
+    if !Path::new(&journal).exists() {
+        return Ok(false);
+    }
+
+    let names = file::read(&journal)?;
+    let mut dropped = false;
+    for name in names.iter().skip(1) {
+        if name == JOURNAL_DROPPED {
+            dropped = true;
+            continue;
+        }
+        let path = Path::new(&db.path).join(name).to_string_lossy().into_owned();
+
+        let _tbl_lock = Lock::exclusive(&path, timeout)?;
+        if dropped {
+            file::remove_file(&path)?;
+            file::remove_file(&file::journal_path(&path))?;
+        }
+        else if Path::new(&file::tmp_path(&path)).exists() {
+            file::commit_tmp(&path)?;
+        }
+    }
+
+    file::remove_file(&journal)?;
+
+    Ok(true)
+}
+
+fn journal_path(db: &Db) -> String {
+    Path::new(&db.path).join(JOURNAL).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::db::{Db, db_error::DbError, field_type::Type, line::Line};
+    use crate::util::file;
+
+    #[test]
+    fn test_commit() {
+        let db = _init_db("/tmp/test_transaction_commit");
+
+        let mut orders = db.table("orders").unwrap();
+        let mut inventory = db.table("inventory").unwrap();
+        orders.insert(_new_line());
+        inventory.insert(_new_line());
+        inventory.insert(_new_line());
+
+        let mut tx = db.transaction();
+        tx.write(&mut orders).unwrap();
+        tx.write(&mut inventory).unwrap();
+
+        // Nothing is written before the commit
+        assert_eq!(db.tables().unwrap().len(), 0);
+
+        tx.commit().unwrap();
+        assert_eq!(db.table("orders").unwrap().get_lines().len(), 1);
+        assert_eq!(db.table("inventory").unwrap().get_lines().len(), 2);
+        assert!(!Path::new(&file::tmp_path("/tmp/test_transaction_commit/orders")).exists());
+        assert!(!Path::new("/tmp/test_transaction_commit/.transaction").exists());
+
+        // The tables are up to date after the commit
+        orders.insert(_new_line());
+        db.write(&mut orders).unwrap();
+        assert_eq!(db.table("orders").unwrap().get_lines().len(), 2);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let db = _init_db("/tmp/test_transaction_rollback");
+
+        let mut orders = db.table("orders").unwrap();
+        orders.insert(_new_line());
+
+        let mut tx = db.transaction();
+        tx.write(&mut orders).unwrap();
+        tx.rollback();
+
+        assert_eq!(db.tables().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_same_table_twice() {
+        let db = _init_db("/tmp/test_transaction_twice");
+
+        let mut first = db.table("orders").unwrap();
+        let mut second = db.table("orders").unwrap();
+
+        let mut tx = db.transaction();
+        tx.write(&mut first).unwrap();
+        assert!(tx.write(&mut second).is_err());
+    }
+
+    #[test]
+    fn test_conflict() {
+        let db = _init_db("/tmp/test_transaction_conflict");
+
+        let mut orders = db.table("orders").unwrap();
+        let mut inventory = db.table("inventory").unwrap();
+        orders.insert(_new_line());
+        inventory.insert(_new_line());
+
+        // Someone else writes the inventory in the meantime
+        let mut other = db.table("inventory").unwrap();
+        other.insert(_new_line());
+        other.insert(_new_line());
+        db.write(&mut other).unwrap();
+
+        let mut tx = db.transaction();
+        tx.write(&mut orders).unwrap();
+        tx.write(&mut inventory).unwrap();
+        assert!(matches!(tx.commit(), Err(DbError::Conflict(name)) if name == "inventory"));
+
+        // None of the tables were written
+        assert_eq!(db.tables().unwrap(), vec!["inventory"]);
+        assert_eq!(db.table("inventory").unwrap().get_lines().len(), 2);
+        assert!(!Path::new(&file::tmp_path("/tmp/test_transaction_conflict/orders")).exists());
+
+        let mut tx = db.transaction();
+        tx.write(&mut orders).unwrap();
+        tx.force_write(&mut inventory).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(db.table("orders").unwrap().get_lines().len(), 1);
+        assert_eq!(db.table("inventory").unwrap().get_lines().len(), 1);
+    }
+
+    #[test]
+    fn test_recover_interrupted_commit() {
+        let p = "/tmp/test_transaction_recover";
+        let db = _init_db(p);
+
+        let mut orders = db.table("orders").unwrap();
+        orders.insert(_new_line());
+        db.write(&mut orders).unwrap();
+
+        // The process dies after writing the journal, while replacing the tables
+        let orders_path = String::from(p) + "/orders";
+        let inventory_path = String::from(p) + "/inventory";
+        file::write_tmp(&orders_path, "#v1.0#", &[]).unwrap();
+        file::write_tmp(&inventory_path, "#v1.0#", &[]).unwrap();
+        file::write(&(String::from(p) + "/.transaction"), "#transaction#", &["inventory".to_owned(), "orders".to_owned()]).unwrap();
+        file::commit_tmp(&inventory_path).unwrap();
+
+        let db = Db::new(p).unwrap();
+        assert_eq!(db.table("orders").unwrap().get_lines().len(), 0);
+        assert_eq!(db.table("inventory").unwrap().get_lines().len(), 0);
+        assert!(!Path::new(&(String::from(p) + "/.transaction")).exists());
+    }
+
+    #[test]
+    fn test_drop_table() {
+        let p = "/tmp/test_transaction_drop";
+        let db = _init_db(p);
+
+        let mut orders = db.table("orders").unwrap();
+        orders.insert(_new_line());
+        db.write(&mut orders).unwrap();
+        let mut inventory = db.table("inventory").unwrap();
+        inventory.insert(_new_line());
+        db.write(&mut inventory).unwrap();
+
+        let mut tx = db.transaction();
+        tx.write(&mut orders).unwrap();
+        assert!(tx.drop_table("orders").is_err());
+        tx.drop_table("inventory").unwrap();
+        tx.commit().unwrap();
+        assert_eq!(db.tables().unwrap(), vec!["orders"]);
+
+        // The process dies after writing the journal, before the table is dropped
+        file::write(&(String::from(p) + "/.transaction"), "#transaction#", &["#dropped#".to_owned(), "orders".to_owned()]).unwrap();
+
+        let db = Db::new(p).unwrap();
+        assert!(db.tables().unwrap().is_empty());
+        assert!(!Path::new(&(String::from(p) + "/.transaction")).exists());
+    }
+
+    #[test]
+    fn test_recover_before_journal() {
+        let p = "/tmp/test_transaction_recover_before";
+        let db = _init_db(p);
+
+        let mut orders = db.table("orders").unwrap();
+        orders.insert(_new_line());
+        db.write(&mut orders).unwrap();
+
+        // The process dies before writing the journal: the transaction never happened
+        file::write_tmp(&(String::from(p) + "/orders"), "#v1.0#", &[]).unwrap();
+
+        let db = Db::new(p).unwrap();
+        assert_eq!(db.table("orders").unwrap().get_lines().len(), 1);
+    }
+
+    fn _init_db(p: &str) -> Db {
+        if Path::new(p).exists() {
+            std::fs::remove_dir_all(p).unwrap();
+        }
+
+        Db::new(p).unwrap()
+    }
+
+    fn _new_line() -> Line {
+        let mut line = Line::new();
+        line.add("col1", Type::from_str("123")).unwrap();
+
+        line
+    }
+}
@@ -1,7 +1,22 @@
-use crate::db::{db_error::DbError, table::Table};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::db::{db_error::DbError, line::Line, table::Table};
+use crate::util::file;
+use crate::util::lock::Lock;
 use v1::table_manager_v1::TableManagerV1;
+use v2::table_manager_v2::TableManagerV2;
 
+mod file_manager;
+mod journal;
 mod v1;
+mod v2;
+
+/// Version of the format of the new tables
+pub const DEFAULT_VERSION: u32 = 1;
+
+/// Most recent version of the format (see upgrade)
+pub const LATEST_VERSION: u32 = 2;
 
 /**
  * The TableManger is used to convert the Table Object to a File and a File to a Table Object
@@ -14,23 +29,218 @@ mod v1;
  * It does not need to manage individual lines.
  * It will basically overwrite a file with the content of the Table Object
  *
- * If needed, it should also manage locks on the table file
+ * It also manages the locks on the table file (shared to read, exclusive to write or drop)
+ *
+ * The manager is selected by the version of the table file (see is_of_type).
+ * A table that does not exist yet uses DEFAULT_VERSION.
  */
-pub fn get_table_manager(base_path: &str, tbl: &str) -> Result<impl TableManager, DbError> {
-    if TableManagerV1::is_of_type(base_path, tbl) {
-        Ok(TableManagerV1::new(base_path, tbl)?)
+pub fn get_table_manager(base_path: &str, tbl: &str, options: &Options) -> Result<Box<dyn TableManager>, DbError> {
+    if TableManagerV2::is_of_type(base_path, tbl) {
+        Ok(Box::new(TableManagerV2::new_with_options(base_path, tbl, options)?))
+    } else if TableManagerV1::is_of_type(base_path, tbl) {
+        Ok(Box::new(TableManagerV1::new_with_options(base_path, tbl, options)?))
     } else {
         // Default version
-        Ok(TableManagerV1::new(base_path, tbl)?)
+        Ok(Box::new(TableManagerV1::new_with_options(base_path, tbl, options)?))
+    }
+}
+
+/**
+ * Get the manager of a specific version of the format, whatever the version of the table file
+ */
+fn get_table_manager_with_version(base_path: &str, tbl: &str, options: &Options, version: u32) -> Result<Box<dyn TableManager>, DbError> {
+    match version {
+        1 => Ok(Box::new(TableManagerV1::new_with_options(base_path, tbl, options)?)),
+        2 => Ok(Box::new(TableManagerV2::new_with_options(base_path, tbl, options)?)),
+        _ => Err(DbError::Custom(format!("The version [{}] of the table format is not supported", version)))
+    }
+}
+
+/**
+ * Rewrite the table with the format of `version`
+ *
+ * The table is read with the manager of its current version and staged with the
+ * manager of the new version. The staged file is read back and compared to the
+ * table before it replaces the table file (with its journal).
+ *
+ * Returns false if the table already has this version. A table cannot be downgraded.
+ */
+pub(crate) fn upgrade(base_path: &str, tbl: &str, options: &Options, version: u32) -> Result<bool, DbError> {
+    let path = Path::new(base_path).join(tbl).to_string_lossy().into_owned();
+    let current = match file::read(&path) {
+        Ok(raw) => content_version(&raw),
+        Err(_) => return Err(DbError::Custom(String::from("The table [") + tbl + "] does not exist"))
+    };
+
+    let target = get_table_manager_with_version(base_path, tbl, options, version)?;
+    match current {
+        Some(current) if current == version => return Ok(false),
+        Some(current) if current > version => {
+            return Err(DbError::Custom(format!("The table [{}] is already in v{}, it cannot be downgraded to v{}", tbl, current, version)));
+        },
+        Some(_) => {},
+        None => return Err(DbError::Custom(String::from("Unknown table format of [") + tbl + "]"))
+    }
+
+    let mut table = get_table_manager(base_path, tbl, options)?.read()?;
+
+    let _lock = target.lock()?;
+    // Fails if the table was written since it was read
+    target.stage(&table, false)?;
+
+    let expected: Vec<Line> = table.get_lines().into_iter().cloned().collect();
+    let staged = file::read(&file::tmp_path(&path)).and_then(|raw| parse_content(&raw));
+    if !matches!(&staged, Ok(lines) if *lines == expected) {
+        target.rollback()?;
+        return Err(DbError::Custom(format!("The table [{}] could not be converted to v{}", tbl, version)));
+    }
+
+    target.commit(&mut table)?;
+
+    Ok(true)
+}
+
+/**
+ * The version of the content of a table file (None if it is not a table)
+ */
+pub(crate) fn content_version(raw: &[String]) -> Option<u32> {
+    if TableManagerV1::is_content_of_type(raw) {
+        Some(1)
+    } else if TableManagerV2::is_content_of_type(raw) {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/**
+ * Parse the content of a table file (with its header, without its journal)
+ */
+pub(crate) fn parse_content(raw: &[String]) -> Result<Vec<Line>, DbError> {
+    match content_version(raw) {
+        Some(1) => TableManagerV1::parse_content(raw),
+        Some(2) => TableManagerV2::parse_content(raw),
+        _ => Err(DbError::Custom(String::from("Unknown table format")))
+    }
+}
+
+/**
+ * Convert the lines to the content of a table file (with its header)
+ */
+pub(crate) fn format_content(lines: &[&Line], version: u32) -> Result<Vec<String>, DbError> {
+    match version {
+        2 => TableManagerV2::format_content(lines),
+        _ => TableManagerV1::format_content(lines)
+    }
+}
+
+/**
+ * The journal that turns the content of a table file (with its header) into the lines
+ *
+ * It is empty when there is nothing to change
+ */
+pub(crate) fn format_journal(raw: &[String], lines: &[&Line]) -> Result<Vec<String>, DbError> {
+    match content_version(raw) {
+        Some(1) => TableManagerV1::format_journal(raw, lines),
+        Some(2) => TableManagerV2::format_journal(raw, lines),
+        _ => Err(DbError::Custom(String::from("Unknown table format")))
+    }
+}
+
+/**
+ * Convert a line to a line of a table file, it can be read without the header of the file
+ */
+pub(crate) fn format_line(line: &Line, version: u32) -> String {
+    match version {
+        2 => TableManagerV2::format_line(line),
+        _ => TableManagerV1::format_line(line)
+    }
+}
+
+/**
+ * Options shared by every TableManager
+ */
+#[derive(Clone)]
+pub struct Options {
+    /**
+     * How long to wait for the lock of a table before giving up
+     */
+    pub lock_timeout: Duration,
+    /**
+     * Append the changes to the journal of the table instead of rewriting the table file
+     */
+    pub journal: bool
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { lock_timeout: Duration::from_secs(5), journal: false }
     }
 }
 
 pub trait TableManager {
-    fn drop(&self) -> Result<(), DbError>;
+    fn drop(&self) -> Result<(), DbError> {
+        let _lock = self.lock()?;
+
+        self.remove()
+    }
+
+    /**
+     * Remove the table files, the lock must already be held (see drop)
+     */
+    fn remove(&self) -> Result<(), DbError>;
 
     #[allow(dead_code)]
-    fn create(&self) -> Result<(), std::io::Error>;
-    fn write(&mut self, tbl: &mut Table) -> Result<bool, DbError>;
+    fn create(&self) -> Result<(), DbError>;
+
+    /**
+     * Take the exclusive lock of the table. It must be held from stage to commit
+     */
+    fn lock(&self) -> Result<Lock, DbError>;
+
+    /**
+     * Write the table to a temporary file, the table file itself is not modified
+     *
+     * Unless `force` is set, it fails with DbError::Conflict when the file
+     * changed since the table was read
+     */
+    fn stage(&self, tbl: &Table, force: bool) -> Result<(), DbError>;
+
+    /**
+     * Replace the table file with the staged file
+     *
+     * Returns true if the table file was created
+     */
+    fn commit(&self, tbl: &mut Table) -> Result<bool, DbError>;
+
+    /**
+     * Discard the staged file
+     */
+    fn rollback(&self) -> Result<(), DbError>;
+
+    /**
+     * Write the table to the file (see stage and commit)
+     */
+    fn write(&mut self, tbl: &mut Table, force: bool) -> Result<bool, DbError> {
+        let _lock = self.lock()?;
+        self.stage(tbl, force)?;
+
+        self.commit(tbl)
+    }
+
     fn read(&self) -> Result<Table, DbError>;
-    fn is_of_type(base_path: &str, tbl: &str) -> bool;
+
+    /**
+     * Read the table, the lock must already be held (see lock)
+     */
+    fn read_locked(&self) -> Result<Table, DbError>;
+
+    /**
+     * Rewrite the table file in its canonical form, and fold the journal into it
+     *
+     * Returns the number of bytes reclaimed
+     */
+    fn compact(&self) -> Result<u64, DbError>;
+
+    fn is_of_type(base_path: &str, tbl: &str) -> bool where Self: Sized;
 }
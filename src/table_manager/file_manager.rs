@@ -0,0 +1,387 @@
+//! The TableManager shared by every version of the table files
+//!
+//! The locks, the version checks, the staging and the journal do not depend on
+//! the format of the file. Each version only supplies its Format: the version
+//! marker (the first line of the file), and how the lines and the journal
+//! entries are converted to and from text.
+use std::marker::PhantomData;
+use std::path::Path;
+
+use crate::db::db_error::DbError;
+use crate::db::field::Field;
+use crate::db::line::Line;
+use crate::db::table::Table;
+use crate::table_manager::{journal, journal::Entry, Options, TableManager};
+use crate::util::file;
+use crate::util::lock::Lock;
+
+/**
+ * The format of a version of the table files
+ */
+pub trait Format {
+    /**
+     * First line of the table files (and of their journals)
+     */
+    const VERSION: &'static str;
+
+    /**
+     * True when a line of the file never ends with a carriage return of a value
+     *
+     * A carriage return at the end of a line then comes from a checkout with CRLF
+     * line endings, it is ignored by the checksums
+     */
+    const STRIP_CR: bool;
+
+    /**
+     * Convert the lines to the content of the file, without the version
+     *
+     * `previous` is the current content of the file (if any), to keep what
+     * the format stores besides the lines
+     */
+    fn lines_to_str(lines: &[&Line], previous: Option<&[String]>) -> Result<Vec<String>, DbError>;
+
+    /**
+     * Convert a line to a line of the file, it can be read without the rest of the file
+     */
+    fn line_to_str(line: &Line) -> String;
+
+    /**
+     * Parse the content of the file (with the version)
+     */
+    fn parse(raw: &[String]) -> Result<Vec<Line>, DbError>;
+
+    /**
+     * Convert a journal Entry to a line of the journal
+     */
+    fn entry_to_str(entry: &Entry) -> String;
+
+    /**
+     * Parse the lines of the journal (with the version, see journal::read)
+     */
+    fn read_journal(raw: &[String]) -> Result<Vec<Entry>, DbError>;
+
+    /**
+     * Tells if a line of the journal ends inside a value (see journal::InValue)
+     */
+    fn in_value(inside: bool, line: &str) -> bool;
+}
+
+/**
+ * Manages the table files of a Format
+ */
+pub struct FileTableManager<F: Format> {
+    pub(crate) tbl_path: String,
+    pub(crate) journal_path: String,
+    tbl_name: String,
+    options: Options,
+    format: PhantomData<F>,
+}
+
+impl<F: Format> TableManager for FileTableManager<F> {
+    fn remove(&self) -> Result<(), DbError> {
+        file::remove_file(&self.tbl_path)?;
+        file::remove_file(&self.journal_path)?;
+
+        Ok(())
+    }
+
+    fn create(&self) -> Result<(), DbError> {
+        let _lock = self.lock()?;
+        if !Path::new(&self.tbl_path).exists() {
+            file::write(&self.tbl_path, F::VERSION, &F::lines_to_str(&[], None)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<Lock, DbError> {
+        Lock::exclusive(&self.tbl_path, self.options.lock_timeout)
+    }
+
+    fn stage(&self, tbl: &Table, force: bool) -> Result<(), DbError> {
+        let (base, journal) = self.read_files()?;
+        if !force {
+            self.check_version(tbl, &base, &journal)?;
+        }
+
+        // A file of another version (being upgraded) has nothing to keep
+        let previous = base.as_deref().filter(|raw| Self::is_content_of_type(raw));
+
+        Self::check_names(&tbl.get_lines())?;
+        let lines = F::lines_to_str(&tbl.get_lines(), previous)?;
+        file::write_tmp(&self.tbl_path, F::VERSION, &lines)?;
+
+        Ok(())
+    }
+
+    fn commit(&self, tbl: &mut Table) -> Result<bool, DbError> {
+        let file_created = !Path::new(&self.tbl_path).exists();
+
+        file::commit_tmp(&self.tbl_path)?;
+        // The journal is now part of the table file
+        file::remove_file(&self.journal_path)?;
+
+        let (base, journal) = self.read_files()?;
+        tbl.set_version(Self::version(&base, &journal));
+        tbl.reset_changes();
+
+        Ok(file_created)
+    }
+
+    fn write(&mut self, tbl: &mut Table, force: bool) -> Result<bool, DbError> {
+        let _lock = self.lock()?;
+        let exists = Path::new(&self.tbl_path).exists();
+        if !force && exists && tbl.changes().is_empty() {
+            // Nothing to write
+            let (base, journal) = self.read_files()?;
+            self.check_version(tbl, &base, &journal)?;
+            return Ok(false);
+        }
+
+        if self.options.journal && !force && exists {
+            self.append(tbl)?;
+            return Ok(false);
+        }
+
+        self.stage(tbl, force)?;
+        self.commit(tbl)
+    }
+
+    fn rollback(&self) -> Result<(), DbError> {
+        file::remove_file(&file::tmp_path(&self.tbl_path))?;
+
+        Ok(())
+    }
+
+    fn read(&self) -> Result<Table, DbError> {
+        let _lock = Lock::shared(&self.tbl_path, self.options.lock_timeout)?;
+
+        self.read_locked()
+    }
+
+    fn read_locked(&self) -> Result<Table, DbError> {
+        let (base, journal) = self.read_files()?;
+
+        let mut table = Table::new(&self.tbl_name, Self::parse(&base, &journal)?)?;
+        table.set_version(Self::version(&base, &journal));
+        table.reset_changes();
+
+        Ok(table)
+    }
+
+    fn compact(&self) -> Result<u64, DbError> {
+        let _lock = self.lock()?;
+        if !Path::new(&self.tbl_path).exists() {
+            return Ok(0);
+        }
+
+        let before = file::size(&self.tbl_path) + file::size(&self.journal_path);
+
+        let (base, journal) = self.read_files()?;
+        let mut table = Table::new(&self.tbl_name, Self::parse(&base, &journal)?)?;
+        self.stage(&table, true)?;
+        self.commit(&mut table)?;
+
+        Ok(before.saturating_sub(file::size(&self.tbl_path)))
+    }
+
+    fn is_of_type(base_path: &str, tbl: &str) -> bool {
+        match Self::get_fullpath(base_path, tbl) {
+            Ok(p) => Self::is_content_of_type(&file::read(&p).unwrap_or_default()),
+            Err(_) => false,
+        }
+    }
+}
+
+impl<F: Format> FileTableManager<F> {
+    #[cfg(test)]
+    pub fn new(base_path: &str, tbl: &str) -> Result<FileTableManager<F>, DbError> {
+        Self::new_with_options(base_path, tbl, &Options::default())
+    }
+
+    pub fn new_with_options(base_path: &str, tbl: &str, options: &Options) -> Result<FileTableManager<F>, DbError> {
+        let fullpath = Self::get_fullpath(base_path, tbl)?;
+
+        let m = FileTableManager {
+            journal_path: file::journal_path(&fullpath),
+            tbl_path: fullpath,
+            tbl_name: String::from(tbl),
+            options: options.clone(),
+            format: PhantomData,
+        };
+
+        Ok(m)
+    }
+
+    /**
+     * True if the content (with its header) has the version of the format
+     *
+     * The version can end with a carriage return (CRLF line endings)
+     */
+    pub fn is_content_of_type(raw: &[String]) -> bool {
+        raw.first().map(|version| version.strip_suffix('\r').unwrap_or(version)) == Some(F::VERSION)
+    }
+
+    /**
+     * Parse the content of a table file (with its header)
+     */
+    pub fn parse_content(raw: &[String]) -> Result<Vec<Line>, DbError> {
+        F::parse(raw)
+    }
+
+    /**
+     * Convert the lines to the content of a table file (with its header)
+     */
+    pub fn format_content(lines: &[&Line]) -> Result<Vec<String>, DbError> {
+        let mut content = vec![String::from(F::VERSION)];
+        content.append(&mut F::lines_to_str(lines, None)?);
+
+        Ok(content)
+    }
+
+    /**
+     * Convert a line to a line of a table file, it can be read without the header
+     */
+    pub fn format_line(line: &Line) -> String {
+        F::line_to_str(line)
+    }
+
+    /**
+     * The journal that turns the content of a table file (with its header) into the lines
+     *
+     * It is empty when there is nothing to change
+     */
+    pub fn format_journal(raw: &[String], lines: &[&Line]) -> Result<Vec<String>, DbError> {
+        let entries = journal::diff(&F::parse(raw)?, lines);
+        if entries.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let entries: Vec<String> = entries.iter().map(F::entry_to_str).collect();
+        Ok(journal::content(&Self::checksum(raw), F::VERSION, &entries))
+    }
+
+    pub(crate) fn get_fullpath(base_path: &str, tbl: &str) -> Result<String, DbError> {
+        match Path::new(base_path).join(tbl).to_str() {
+            Some(p) => Ok(String::from(p)),
+            None => Err(DbError::Custom(String::from("The path to the table is empty")))
+        }
+    }
+
+    /**
+     * Read the raw table file (None if it does not exist) and its journal
+     */
+    fn read_files(&self) -> Result<(Option<Vec<String>>, Vec<String>), DbError> {
+        let base = file::read(&self.tbl_path).ok();
+        let journal = match &base {
+            Some(raw) => journal::read(&self.journal_path, &Self::checksum(raw), F::in_value)?,
+            None => Vec::new()
+        };
+
+        Ok((base, journal))
+    }
+
+    /**
+     * The checksum of the lines (see file::checksum and Format::STRIP_CR)
+     */
+    fn checksum(raw: &[String]) -> String {
+        if F::STRIP_CR {
+            let raw: Vec<String> = raw.iter().map(|line| String::from(line.strip_suffix('\r').unwrap_or(line))).collect();
+            return file::checksum(&raw);
+        }
+
+        file::checksum(raw)
+    }
+
+    fn parse(base: &Option<Vec<String>>, journal: &[String]) -> Result<Vec<Line>, DbError> {
+        let mut lines = vec![];
+        if let Some(raw) = base {
+            if !raw.is_empty() {
+                lines = F::parse(raw)?;
+            }
+        }
+
+        if !journal.is_empty() {
+            lines = journal::replay(lines, F::read_journal(journal)?);
+        }
+
+        Ok(lines)
+    }
+
+    fn version(base: &Option<Vec<String>>, journal: &[String]) -> Option<String> {
+        base.as_ref().map(|raw| Self::checksum(&[raw.as_slice(), journal].concat()))
+    }
+
+    fn check_version(&self, tbl: &Table, base: &Option<Vec<String>>, journal: &[String]) -> Result<(), DbError> {
+        if Self::version(base, journal).as_deref() != tbl.get_version() {
+            return Err(DbError::Conflict(String::from(tbl.get_name())));
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Append the changes to the journal (the lock must be held)
+     *
+     * Only the lines inserted, modified or deleted since the table was read are written
+     */
+    fn append(&self, tbl: &mut Table) -> Result<(), DbError> {
+        let (base, journal) = self.read_files()?;
+        self.check_version(tbl, &base, &journal)?;
+
+        let entries = Self::entries(tbl);
+        for entry in &entries {
+            if let Entry::Upsert(line) = entry {
+                Self::check_names(&[line])?;
+            }
+        }
+
+        let entries: Vec<String> = entries.iter().map(F::entry_to_str).collect();
+        if !entries.is_empty() {
+            let checksum = Self::checksum(base.as_deref().unwrap_or_default());
+            journal::append(&self.journal_path, &checksum, F::VERSION, &entries, F::in_value)?;
+        }
+
+        if journal::should_compact(&self.tbl_path, &self.journal_path) {
+            self.stage(tbl, true)?;
+            self.commit(tbl)?;
+        }
+        else {
+            let (base, journal) = self.read_files()?;
+            tbl.set_version(Self::version(&base, &journal));
+            tbl.reset_changes();
+        }
+
+        Ok(())
+    }
+
+    fn entries(tbl: &Table) -> Vec<Entry> {
+        let changes = tbl.changes();
+
+        let mut entries = Vec::new();
+        for id in changes.inserted.iter().chain(changes.modified.iter()) {
+            if let Some(line) = tbl.get(id) {
+                entries.push(Entry::Upsert(line.clone()));
+            }
+        }
+
+        for id in changes.deleted {
+            entries.push(Entry::Delete(id));
+        }
+
+        entries
+    }
+
+    /**
+     * Fail if a field name cannot be written (see Field::check_name)
+     */
+    fn check_names(lines: &[&Line]) -> Result<(), DbError> {
+        for line in lines {
+            for field in line.get_fields() {
+                Field::check_name(field.get_name())?;
+            }
+        }
+
+        Ok(())
+    }
+}
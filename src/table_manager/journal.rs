@@ -0,0 +1,382 @@
+//! Append-only journal of the changes made to a table
+//!
+//! In journaled mode, a write appends the lines that changed to the journal of
+//! the table instead of rewriting the whole table file. The journal is replayed
+//! on top of the table file when the table is read. Once the journal is bigger
+//! than the table file, it is folded back into the table file (see should_compact).
+//!
+//! The journal starts with BASE_PREFIX followed by the checksum of the table
+//! file it applies to. When the table file is rewritten, the journal no longer
+//! applies to it and is ignored, even if the process died before removing it.
+//!
+//! Every write appends one batch of entries, followed by BATCH_END. A batch
+//! without its BATCH_END (the process died while appending) is ignored. An entry
+//! can span multiple lines when a value has line breaks, a BATCH_END line inside
+//! a value is not the end of a batch (see InValue).
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::Path;
+
+use uuid::Uuid;
+
+use crate::db::db_error::DbError;
+use crate::db::line::Line;
+use crate::util::file;
+
+/// Line written after each batch of entries
+pub const BATCH_END: &str = "#";
+
+/// Start of the first line of the journal, followed by the checksum of the table file
+const BASE_PREFIX: &str = "@";
+
+/// The journal is never compacted below this size (in bytes)
+const MIN_COMPACT_SIZE: u64 = 4096;
+
+/**
+ * Tells if a line of the journal ends inside a value, knowing if it started inside one
+ *
+ * It is given by the format of the entries, to find the real ends of the batches
+ */
+pub type InValue = fn(bool, &str) -> bool;
+
+/**
+ * A change to a table
+ */
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Entry {
+    /**
+     * Insert the line, or replace the line with the same id
+     */
+    Upsert(Line),
+    /**
+     * Delete the line with this id
+     */
+    Delete(Uuid)
+}
+
+/**
+ * Apply the entries to the lines
+ */
+pub fn replay(lines: Vec<Line>, entries: Vec<Entry>) -> Vec<Line> {
+    let mut lines: Vec<Option<Line>> = lines.into_iter().map(Some).collect();
+    let mut index: HashMap<Uuid, usize> = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(line) = line {
+            index.insert(*line.get_id(), i);
+        }
+    }
+
+    for entry in entries {
+        match entry {
+            Entry::Upsert(line) => {
+                match index.get(line.get_id()) {
+                    Some(i) => lines[*i] = Some(line),
+                    None => {
+                        index.insert(*line.get_id(), lines.len());
+                        lines.push(Some(line));
+                    }
+                }
+            },
+            Entry::Delete(id) => {
+                if let Some(i) = index.remove(&id) {
+                    lines[i] = None;
+                }
+            }
+        }
+    }
+
+    lines.into_iter().flatten().collect()
+}
+
+/**
+ * The entries that turn the lines into the target lines
+ */
+pub fn diff(lines: &[Line], target: &[&Line]) -> Vec<Entry> {
+    let by_id: HashMap<&Uuid, &Line> = lines.iter().map(|l| (l.get_id(), l)).collect();
+
+    let mut entries = Vec::new();
+    for line in target {
+        if by_id.get(line.get_id()) != Some(line) {
+            entries.push(Entry::Upsert((*line).clone()));
+        }
+    }
+
+    let ids: Vec<&Uuid> = target.iter().map(|l| l.get_id()).collect();
+    for line in lines {
+        if !ids.contains(&line.get_id()) {
+            entries.push(Entry::Delete(*line.get_id()));
+        }
+    }
+
+    entries
+}
+
+/**
+ * The content of a journal with a single batch of entries (already converted to strings)
+ *
+ * `base` is the checksum of the table file (see append)
+ */
+pub fn content(base: &str, header: &str, entries: &[String]) -> Vec<String> {
+    let mut content = vec![String::from(BASE_PREFIX) + base, String::from(header)];
+    content.extend(entries.iter().cloned());
+    content.push(String::from(BATCH_END));
+
+    content
+}
+
+/**
+ * Read the journal, without the incomplete batch at the end (if any)
+ *
+ * `base` is the checksum of the table file. Returns an empty list when there
+ * is no journal, or when the journal does not apply to the table file.
+ */
+pub fn read(path: &str, base: &str, in_value: InValue) -> Result<Vec<String>, DbError> {
+    if !Path::new(path).exists() {
+        return Ok(Vec::new());
+    }
+
+    let text = std::fs::read_to_string(path)?;
+    if !applies_to(&text, base) {
+        return Ok(Vec::new());
+    }
+
+    let len = committed_len(&text, in_value);
+    Ok(text[..len].split_terminator('\n').skip(1).map(String::from).collect())
+}
+
+/**
+ * Append a batch of entries (already converted to strings) to the journal
+ *
+ * `base` is the checksum of the table file. The journal is started over
+ * (with the header) when it does not apply to the table file.
+ */
+pub fn append(path: &str, base: &str, header: &str, entries: &[String], in_value: InValue) -> Result<(), DbError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+
+    // The whole journal is read, the end of the file can be a BATCH_END inside a value
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+
+    let len = if applies_to(&text, base) {
+        // Remove what is left of an interrupted append
+        committed_len(&text, in_value) as u64
+    }
+    else {
+        0
+    };
+    if len != text.len() as u64 {
+        file.set_len(len)?;
+    }
+
+    let mut batch = String::new();
+    if len == 0 {
+        batch.push_str(BASE_PREFIX);
+        batch.push_str(base);
+        batch.push('\n');
+        batch.push_str(header);
+        batch.push('\n');
+    }
+
+    for entry in entries {
+        batch.push_str(entry);
+        batch.push('\n');
+    }
+    batch.push_str(BATCH_END);
+    batch.push('\n');
+
+    file.write_all(batch.as_bytes())?;
+    file.sync_data()?;
+
+    Ok(())
+}
+
+/**
+ * The journal should be folded into the table file once it is bigger than the table file
+ */
+pub fn should_compact(tbl_path: &str, journal_path: &str) -> bool {
+    let journal_size = file::size(journal_path);
+
+    journal_size > MIN_COMPACT_SIZE && journal_size > file::size(tbl_path)
+}
+
+fn applies_to(text: &str, base: &str) -> bool {
+    text.lines().next() == Some(&(String::from(BASE_PREFIX) + base))
+}
+
+/**
+ * Length of the text without the incomplete batch at the end
+ *
+ * Only the first two lines (the base and the header) are kept when no batch is complete
+ */
+fn committed_len(text: &str, in_value: InValue) -> usize {
+    let mut len = 0;
+    let mut pos = 0;
+    let mut inside = false;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        pos += line.len();
+        let line = match line.strip_suffix('\n') {
+            Some(line) => line,
+            // The last line was not fully written
+            None => break
+        };
+
+        if i < 2 {
+            if i == 1 {
+                len = pos;
+            }
+        }
+        else if !inside && line.strip_suffix('\r').unwrap_or(line) == BATCH_END {
+            len = pos;
+        }
+        else {
+            inside = in_value(inside, line);
+        }
+    }
+
+    len
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use crate::db::{field::Field, line::Line};
+
+    use super::*;
+
+    #[test]
+    fn test_replay() {
+        let id1 = Uuid::new_v4();
+        let id2 = Uuid::new_v4();
+        let id3 = Uuid::new_v4();
+
+        let before = vec![
+            Line::new_with_id(id1, vec![Field::new_str("name", "Mike")]),
+            Line::new_with_id(id2, vec![Field::new_str("name", "Sean")]),
+        ];
+        let updated = Line::new_with_id(id2, vec![Field::new_str("name", "Simon")]);
+        let inserted = Line::new_with_id(id3, vec![Field::new_int("number", 12)]);
+        let entries = vec![Entry::Upsert(updated.clone()), Entry::Upsert(inserted.clone()), Entry::Delete(id1)];
+
+        let lines = replay(before, entries);
+        assert_eq!(lines, vec![updated, inserted]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let id1 = Uuid::new_v4();
+        let id2 = Uuid::new_v4();
+        let kept = Line::new_with_id(Uuid::new_v4(), vec![Field::new_str("name", "Mike")]);
+        let before = vec![
+            kept.clone(),
+            Line::new_with_id(id1, vec![Field::new_str("name", "Sean")]),
+            Line::new_with_id(id2, vec![Field::new_str("name", "Simon")]),
+        ];
+        let updated = Line::new_with_id(id2, vec![Field::new_str("name", "Mark")]);
+        let inserted = Line::new_with_id(Uuid::new_v4(), vec![Field::new_int("number", 12)]);
+        let target = vec![&kept, &updated, &inserted];
+
+        let entries = diff(&before, &target);
+        assert_eq!(entries, vec![Entry::Upsert(updated.clone()), Entry::Upsert(inserted.clone()), Entry::Delete(id1)]);
+        assert_eq!(replay(before, entries), vec![kept, updated, inserted]);
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let path = "/tmp/test_journal_append";
+        file::remove_file(path).unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap().len(), 0);
+
+        append(path, "base", "#v1.0#", &["+a".to_owned(), "-b".to_owned()], _in_value).unwrap();
+        append(path, "base", "#v1.0#", &["+c".to_owned()], _in_value).unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap(), vec!["#v1.0#", "+a", "-b", "#", "+c", "#"]);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "@base\n#v1.0#\n+a\n-b\n#\n+c\n#\n");
+
+        // The same content as a single append
+        file::remove_file(path).unwrap();
+        append(path, "base", "#v1.0#", &["+a".to_owned(), "-b".to_owned()], _in_value).unwrap();
+        assert_eq!(file::read(path).unwrap(), content("base", "#v1.0#", &["+a".to_owned(), "-b".to_owned()]));
+
+        file::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_other_base() {
+        let path = "/tmp/test_journal_other_base";
+        file::remove_file(path).unwrap();
+
+        append(path, "base", "#v1.0#", &["+a".to_owned()], _in_value).unwrap();
+
+        // The table file was rewritten, but the journal was not removed
+        assert_eq!(read(path, "other", _in_value).unwrap().len(), 0);
+
+        append(path, "other", "#v1.0#", &["+b".to_owned()], _in_value).unwrap();
+        assert_eq!(read(path, "other", _in_value).unwrap(), vec!["#v1.0#", "+b", "#"]);
+        assert_eq!(read(path, "base", _in_value).unwrap().len(), 0);
+
+        file::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_append() {
+        let path = "/tmp/test_journal_interrupted";
+        file::remove_file(path).unwrap();
+
+        // The process dies in the middle of the first batch
+        std::fs::write(path, "@base\n#v1.0#\n+a\n+").unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap(), vec!["#v1.0#"]);
+
+        append(path, "base", "#v1.0#", &["+b".to_owned()], _in_value).unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap(), vec!["#v1.0#", "+b", "#"]);
+
+        // The process dies in the middle of the second batch
+        let mut text = std::fs::read_to_string(path).unwrap();
+        text.push_str("+c\n-d");
+        std::fs::write(path, text).unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap(), vec!["#v1.0#", "+b", "#"]);
+
+        append(path, "base", "#v1.0#", &["+e".to_owned()], _in_value).unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap(), vec!["#v1.0#", "+b", "#", "+e", "#"]);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "@base\n#v1.0#\n+b\n#\n+e\n#\n");
+
+        // The process dies while creating the journal
+        std::fs::write(path, "@ba").unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap().len(), 0);
+        append(path, "base", "#v1.0#", &["+f".to_owned()], _in_value).unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap(), vec!["#v1.0#", "+f", "#"]);
+
+        file::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_batch_end_in_value() {
+        let path = "/tmp/test_journal_batch_end_in_value";
+        file::remove_file(path).unwrap();
+
+        append(path, "base", "#v1.0#", &["+\"a".to_owned(), "#".to_owned(), "b\"".to_owned()], _in_value).unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap(), vec!["#v1.0#", "+\"a", "#", "b\"", "#"]);
+
+        // The process dies after the BATCH_END inside the value
+        let committed = std::fs::read_to_string(path).unwrap();
+        std::fs::write(path, committed.clone() + "+\"c\n#\n").unwrap();
+        assert_eq!(read(path, "base", _in_value).unwrap(), vec!["#v1.0#", "+\"a", "#", "b\"", "#"]);
+
+        append(path, "base", "#v1.0#", &["+d".to_owned()], _in_value).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), committed + "+d\n#\n");
+
+        file::remove_file(path).unwrap();
+    }
+
+    /**
+     * The values are between quotes in the tests
+     */
+    fn _in_value(inside: bool, line: &str) -> bool {
+        inside ^ (line.matches('"').count() % 2 == 1)
+    }
+}
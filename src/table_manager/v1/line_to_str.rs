@@ -2,6 +2,7 @@
 use crate::db::field_type::Type;
 use crate::db::line::Line;
 use crate::db::field::Field;
+use crate::table_manager::journal::Entry;
 
 /// Converts a Line to a string with the V1 format
 pub fn line_to_str(line: &Line) -> String {
@@ -19,6 +20,14 @@ pub fn line_to_str(line: &Line) -> String {
     text
 }
 
+/// Converts a journal Entry to a string with the V1 format
+pub fn entry_to_str(entry: &Entry) -> String {
+    match entry {
+        Entry::Upsert(line) => String::from("+") + &line_to_str(line),
+        Entry::Delete(id) => String::from("-") + &line_to_str(&Line::new_with_id(*id, vec![]))
+    }
+}
+
 fn fields_to_str(fields: &Vec<Field>) -> String {
     let mut text = String::from("");
     
@@ -43,7 +52,8 @@ fn append_field(fields: &mut String, name: &str, value: &str) {
     fields.push_str(name);
     fields.push_str(":");
     fields.push_str("\"");
-    fields.push_str(&value.to_string());
+    // A quote in the value is doubled (see Parser::loop_for_value)
+    fields.push_str(&value.replace('"', "\"\""));
     fields.push_str("\"");
 }
 
@@ -55,7 +65,8 @@ mod test {
     use uuid::Uuid;
 
     use crate::db::{line::Line, field::Field};
-    use crate::table_manager::v1::line_to_str::line_to_str;
+    use crate::table_manager::journal::Entry;
+    use crate::table_manager::v1::line_to_str::{entry_to_str, line_to_str};
 
     #[test]
     fn test_ln_to_str() {
@@ -74,4 +85,22 @@ mod test {
     
         assert_eq!(line_to_str(&line), expect);
     }
-}
+
+    #[test]
+    fn test_ln_to_str_escaped() {
+        let uid = "e88954bd-3ae5-4cc5-a1c5-839926790dda";
+        let line = Line::new_with_id(uuid::Uuid::parse_str(uid).unwrap(), vec![ Field::new_str("col1", "Using \" in a text") ]);
+        let expect = "[_id:\"".to_owned() + uid + "\" col1:\"Using \"\" in a text\":string]";
+
+        assert_eq!(line_to_str(&line), expect);
+    }
+
+    #[test]
+    fn test_entry_to_str() {
+        let uid = "e88954bd-3ae5-4cc5-a1c5-839926790dda";
+        let line = Line::new_with_id(uuid::Uuid::parse_str(uid).unwrap(), vec![ Field::new_str("col1", "1") ]);
+
+        assert_eq!(entry_to_str(&Entry::Upsert(line)), "+[_id:\"".to_owned() + uid + "\" col1:\"1\":string]");
+        assert_eq!(entry_to_str(&Entry::Delete(Uuid::parse_str(uid).unwrap())), "-[_id:\"".to_owned() + uid + "\" ]");
+    }
+}
\ No newline at end of file
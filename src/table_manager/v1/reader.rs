@@ -5,8 +5,9 @@ use parser::Parser;
 use lexer::Lexer;
 
 use crate::db::{line::Line, db_error::DbError};
+use crate::table_manager::journal::Entry;
 
-pub fn read(lines: &Vec<String>) -> Result<Vec<Line>, DbError> {
+pub fn read(lines: &[String]) -> Result<Vec<Line>, DbError> {
     let str_lines = &vec_to_str(lines);
     let mut lexer = Lexer::new(str_lines);
 
@@ -16,12 +17,87 @@ pub fn read(lines: &Vec<String>) -> Result<Vec<Line>, DbError> {
     Ok(p.lines)
 }
 
-fn vec_to_str(lines: &Vec<String>) -> String {
-    let mut s = String::from("");
+pub fn read_journal(lines: &[String]) -> Result<Vec<Entry>, DbError> {
+    let str_lines = &vec_to_str(lines);
+    let mut lexer = Lexer::new(str_lines);
+
+    let p = Parser::new_journal(&mut lexer)?;
+
+    Ok(p.entries)
+}
+
+/**
+ * Tells if a line of the journal ends inside a value (see journal::InValue)
+ *
+ * The values are between quotes, and a quote inside a value is doubled. The field
+ * names cannot have quotes, so every odd quote starts or ends a value.
+ */
+pub fn in_value(inside: bool, line: &str) -> bool {
+    inside ^ (line.matches('"').count() % 2 == 1)
+}
+
+/**
+ * Join the lines of the file, the line breaks can be part of a value
+ */
+fn vec_to_str(lines: &[String]) -> String {
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use uuid::Uuid;
+
+    use crate::db::{field::Field, line::Line};
+    use crate::table_manager::journal::Entry;
+    use crate::table_manager::v1::line_to_str::{entry_to_str, line_to_str};
 
-    for line in lines {
-        s.push_str(line);
+    use super::{read, read_journal};
+
+    proptest! {
+        #[test]
+        fn test_round_trip(fields in prop::collection::vec((_name(), _value()), 0..5), number in any::<i64>(), flag in any::<bool>()) {
+            // The index keeps the names unique
+            let mut fields: Vec<Field> = fields.iter().enumerate().map(|(i, (n, v))| Field::new_str(&format!("{}{}", n, i), v)).collect();
+            fields.push(Field::new_int("number", number));
+            fields.push(Field::new_bool("flag", flag));
+            let lines = vec![Line::new_with_id(Uuid::new_v4(), fields), Line::new_with_id(Uuid::new_v4(), vec![])];
+
+            let text: Vec<String> = lines.iter().map(line_to_str).collect();
+            prop_assert_eq!(read(&_file_lines("#v1.0#", &text)).unwrap(), lines);
+        }
+
+        #[test]
+        fn test_journal_round_trip(value in _value()) {
+            let id = Uuid::new_v4();
+            let entries = vec![Entry::Upsert(Line::new_with_id(id, vec![Field::new_str("col1", &value)])), Entry::Delete(id)];
+
+            let mut text: Vec<String> = entries.iter().map(entry_to_str).collect();
+            text.push(String::from("#"));
+            prop_assert_eq!(read_journal(&_file_lines("#v1.0#", &text)).unwrap(), entries);
+        }
+    }
+
+    /**
+     * Any text, with the quotes, the separators, the line breaks and the multibyte chars
+     */
+    fn _value() -> impl Strategy<Value = String> {
+        any::<String>()
+    }
+
+    /**
+     * A valid field name (see Field::check_name), with multibyte chars
+     */
+    fn _name() -> impl Strategy<Value = String> {
+        "[a-zA-Zàéèêçœ日本_\\-.]{1,10}"
     }
 
-    s
+    /**
+     * The lines as they are read back from the file (see file::read)
+     */
+    fn _file_lines(header: &str, text: &[String]) -> Vec<String> {
+        let content = String::from(header) + "\n" + &text.join("\n") + "\n";
+
+        content.split_terminator('\n').map(String::from).collect()
+    }
 }
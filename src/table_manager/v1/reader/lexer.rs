@@ -14,21 +14,21 @@ impl<'a> Lexer<'a> {
     }
 
     fn init(&mut self, text: &'a str) {
+        // Byte offset where the current token starts
         let mut start_token = 0;
-        let mut i = 0;
 
-        for c in text.chars() {
+        // The separators are all ASCII, i is a byte offset (it is not the index of the char)
+        for (i, c) in text.char_indices() {
             if c == ' ' || c == '[' || c == ']' || c == '"' || c == '#' || c == ':' {
-                if start_token + 1 < i {
+                if start_token < i {
                     // Push token
-                    self.tokens.push(&text[start_token+1..i]);
+                    self.tokens.push(&text[start_token..i]);
                 }
 
                 // Push token seprator
                 self.tokens.push(&text[i..i+1]);
-                start_token = i;
+                start_token = i + 1;
             }
-            i += 1;
         }
     }
 
@@ -192,4 +192,19 @@ fn test_init_version_only() {
     let lexer = Lexer::new(s);
 
     assert_eq!(lexer.count(), 3);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_init_multibyte() {
+    let s = "#v1.0#[_id:\"1\" prénom:\"Hélène Bérubé\":string café:\"€ · 日本\":string]";
+    let lexer = Lexer::new(s);
+
+    assert_eq!(lexer.peek_at(10).unwrap(), "prénom");
+    assert_eq!(lexer.peek_at(13).unwrap(), "Hélène");
+    assert_eq!(lexer.peek_at(14).unwrap(), " ");
+    assert_eq!(lexer.peek_at(15).unwrap(), "Bérubé");
+    assert_eq!(lexer.peek_at(20).unwrap(), "café");
+    assert_eq!(lexer.peek_at(23).unwrap(), "€");
+    assert_eq!(lexer.peek_at(25).unwrap(), "·");
+    assert_eq!(lexer.peek_at(27).unwrap(), "日本");
+}
@@ -1,28 +1,37 @@
-use chrono::DateTime;
-use chrono::Utc;
-use rust_decimal::Decimal;
-use rust_decimal::prelude::*;
 use uuid::Uuid;
 
 use super::lexer::Lexer;
 use crate::db::db_error::DbError;
 use crate::db::line::Line;
 use crate::db::field::Field;
+use crate::db::field_type::Type;
+use crate::table_manager::journal::{self, Entry};
 
 pub struct Parser {
     version: String,
-    pub lines: Vec<Line>
+    pub lines: Vec<Line>,
+    pub entries: Vec<Entry>
 }
 
 impl Parser {
     pub fn new(lexer: &mut Lexer) -> Result<Parser, DbError> {
-        let mut p = Parser { version: String::from(""), lines: vec![] };
+        let mut p = Parser { version: String::from(""), lines: vec![], entries: vec![] };
         p.init(lexer)?;
 
         Ok(p)
     }
 
-    pub fn init(&mut self, lexer: &mut Lexer) -> Result<(), DbError> {
+    /**
+     * Parse a journal (see table_manager::journal)
+     */
+    pub fn new_journal(lexer: &mut Lexer) -> Result<Parser, DbError> {
+        let mut p = Parser { version: String::from(""), lines: vec![], entries: vec![] };
+        p.init_journal(lexer)?;
+
+        Ok(p)
+    }
+
+    fn parse_version(&mut self, lexer: &mut Lexer) -> Result<(), DbError> {
         lexer.consume_and_check("#")?;
 
         lexer.consume_and_check("v1.0")?;
@@ -30,9 +39,59 @@ impl Parser {
 
         lexer.consume_and_check("#")?;
 
+        Ok(())
+    }
+
+    fn init_journal(&mut self, lexer: &mut Lexer) -> Result<(), DbError> {
+        self.parse_version(lexer)?;
+
+        let mut batch: Vec<Entry> = vec![];
         loop {
-            match lexer.peek() {
+            // The entries are separated by line breaks
+            match lexer.peek().map(str::trim) {
                 None => break,
+                Some("") => {
+                    lexer.consume();
+                },
+                Some(c) => {
+                    if c == "+" {
+                        lexer.consume();
+                        batch.push(Entry::Upsert(Self::parse_line(lexer)?));
+                    }
+                    else if c == "-" {
+                        lexer.consume();
+                        let line = Self::parse_line(lexer)?;
+                        batch.push(Entry::Delete(*line.get_id()));
+                    }
+                    else if c == journal::BATCH_END {
+                        lexer.consume();
+                        self.entries.append(&mut batch);
+                    }
+                    else {
+                        let msg = String::from("Unexpected token! [") + c + "]";
+                        return Err(DbError::Custom(msg));
+                    }
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            return Err(DbError::Custom(String::from("The last batch of the journal is incomplete")));
+        }
+
+        Ok(())
+    }
+
+    pub fn init(&mut self, lexer: &mut Lexer) -> Result<(), DbError> {
+        self.parse_version(lexer)?;
+
+        loop {
+            // The lines are separated by line breaks
+            match lexer.peek().map(str::trim) {
+                None => break,
+                Some("") => {
+                    lexer.consume();
+                },
                 Some(c) => {
                     if c == "[" {
                         self.lines.push(Self::parse_line(lexer)?);
@@ -95,51 +154,8 @@ impl Parser {
         lexer.consume_and_check(":")?;
 
         let type_name = lexer.consume_err_if_none()?;
-        let field = match type_name {
-            "string" => Field::new_str(name, value),
-            "integer" => Field::new_int(name, Parser::str_to_int(value)?),
-            "decimal" => Field::new_decimal(name, Parser::str_to_decimal(value)?),
-            "boolean" => Field::new_bool(name, Parser::str_to_bool(value)),
-            "datetime" => Field::new_datetime(name, Parser::str_to_datetime(value)?),
-            _ => {
-                let msg = String::from("The type [") + type_name + "] is not supported";
-                return Err(DbError::Custom(msg));
-            }
-        };
-
-        Ok(field)
-    }
-
-    fn str_to_int(value: &str) -> Result<i64, DbError> {
-        match i64::from_str(value) {
-            Ok(i) => Ok(i),
-            Err(error) => Err(DbError::Custom(error.to_string()))
-        }
-    }
-
-    fn str_to_datetime(value: &str) -> Result<DateTime<Utc>, DbError> {
-        let dt = match DateTime::from_str(value) {
-            Ok(v) => v,
-            Err(_) => return Err(DbError::Custom(String::from("Error parsing [") + value + "] into a DateTime"))
-        };
 
-        Ok(dt)
-    }
-
-    fn str_to_bool(value: &str) -> bool {
-        let mut b = false;
-        if value == "true" {
-            b = true;
-        }
-
-        b
-    }
-
-    fn str_to_decimal(value: &str) -> Result<Decimal, DbError> {
-        match Decimal::from_str(value) {
-            Ok(i) => Ok(i),
-            Err(error) => Err(DbError::Custom(error.to_string()))
-        }
+        Ok(Field::new(name, Type::parse(type_name, value)?))
     }
 
     fn loop_for_value(lexer: &mut Lexer) -> Result<String, DbError> {
@@ -213,6 +229,8 @@ mod test {
     use uuid::Uuid;
 
     use crate::db::db_error::DbError;
+    use crate::db::field_type::Type;
+    use crate::table_manager::journal::Entry;
     use crate::table_manager::v1::reader::{lexer::Lexer, parser::Parser};
 
     #[test]
@@ -228,10 +246,11 @@ mod test {
 
         assert_eq!(dt, dt_expect.to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
 
-        assert_eq!(Parser::str_to_bool(bool), false);
-        assert_eq!(Parser::str_to_decimal(dec).unwrap(), Decimal::from_str("23.112").unwrap());
-        assert_eq!(Parser::str_to_int(int).unwrap(), 12);
-        assert_eq!(Parser::str_to_datetime(dt).unwrap().timestamp_millis(), dt_expect.timestamp_millis());
+        // The values are converted by Type::parse, like in the other formats
+        assert_eq!(Type::parse("boolean", bool).unwrap().to_bool().unwrap(), false);
+        assert_eq!(Type::parse("decimal", dec).unwrap().to_decimal().unwrap(), &Decimal::from_str("23.112").unwrap());
+        assert_eq!(Type::parse("integer", int).unwrap().to_int().unwrap(), 12);
+        assert_eq!(Type::parse("datetime", dt).unwrap().to_datetime().unwrap().timestamp_millis(), dt_expect.timestamp_millis());
     }
 
     #[test]
@@ -259,6 +278,34 @@ mod test {
     
         assert_eq!(lines[2].get_fields()[0].get().to_string(), "Brackets in a value []");
     }
+
+    #[test]
+    fn test_journal() {
+        let to_parse = String::from("#v1.0#+[_id:\"5435c914-a918-4cc7-8354-e55ff04d9e25\" col1:\"123\":integer]-[_id:\"3b3f4537-1b8b-4577-999f-e650ea76e190\" ]#") +
+                                                "-[_id:\"5435c914-a918-4cc7-8354-e55ff04d9e25\" ]#";
+
+        let mut l = Lexer::new(&to_parse);
+        let p = Parser::new_journal(&mut l).unwrap();
+
+        assert_eq!(p.entries.len(), 3);
+        match &p.entries[0] {
+            Entry::Upsert(line) => {
+                assert_eq!(line.get_id(), &Uuid::parse_str("5435c914-a918-4cc7-8354-e55ff04d9e25").unwrap());
+                assert_eq!(line.get_fields()[0].get().to_string(), "123");
+            },
+            _ => panic!("Expected an upsert")
+        }
+        assert_eq!(p.entries[1], Entry::Delete(Uuid::parse_str("3b3f4537-1b8b-4577-999f-e650ea76e190").unwrap()));
+        assert_eq!(p.entries[2], Entry::Delete(Uuid::parse_str("5435c914-a918-4cc7-8354-e55ff04d9e25").unwrap()));
+
+        // A batch must be closed
+        let mut l = Lexer::new("#v1.0#-[_id:\"3b3f4537-1b8b-4577-999f-e650ea76e190\" ]");
+        assert!(Parser::new_journal(&mut l).is_err());
+
+        // Unknown operation
+        let mut l = Lexer::new("#v1.0#*[_id:\"3b3f4537-1b8b-4577-999f-e650ea76e190\" ]#");
+        assert_eq!(_unwrap_custom_error(&Parser::new_journal(&mut l).unwrap_err()), "Unexpected token! [*]");
+    }
     
     #[test]
     fn test_invalid_format() {
@@ -1,110 +1,58 @@
 use std::fs::File;
 use std::io::{self, BufRead};
-use std::vec;
 
 use crate::db::db_error::DbError;
+#[cfg(test)]
+use crate::db::field::Field;
 use crate::db::field_type::Type;
 use crate::db::line::Line;
-use crate::db::table::Table;
+#[cfg(test)]
+use crate::table_manager::Options;
 use crate::table_manager::TableManager;
+use crate::table_manager::file_manager::{FileTableManager, Format};
+use crate::table_manager::journal::Entry;
+#[cfg(test)]
 use crate::util::file;
 
-use super::line_to_str::line_to_str;
+use super::line_to_str::{entry_to_str, line_to_str};
 use super::reader;
 
 const TBL_VERSION: &str = "#v1.0#";
 
-pub struct TableManagerV1 {
-    tbl_path: String,
-    tbl_name: String,
-}
-
-impl TableManager for TableManagerV1 {
-    fn drop(&self) -> Result<(), DbError> {
-        file::remove_file(&self.tbl_path)?;
-
-        Ok(())
-    }
+/**
+ * The V1 format: one `[name:"value"]` line per record, after the version
+ */
+pub struct FormatV1;
 
-    fn create(&self) -> Result<(), std::io::Error> {
-        let path = std::path::Path::new(&self.tbl_path);
-        if !path.exists() {
-            file::insert(&self.tbl_path, TBL_VERSION)?;
-        }
-
-        Ok(())
-    }
+pub type TableManagerV1 = FileTableManager<FormatV1>;
 
-    fn write(&mut self, tbl: &mut Table) -> Result<bool, DbError> {
-        let lines = TableManagerV1::convert_to_str(&tbl.get_lines());
-        let file_created = file::write(&self.tbl_path, TBL_VERSION, &lines)?;
+impl Format for FormatV1 {
+    const VERSION: &'static str = TBL_VERSION;
+    // A value can end a line with a carriage return
+    const STRIP_CR: bool = false;
 
-        Ok(file_created)
+    fn lines_to_str(lines: &[&Line], _previous: Option<&[String]>) -> Result<Vec<String>, DbError> {
+        Ok(lines.iter().map(|line| line_to_str(line)).collect())
     }
 
-    fn read(&self) -> Result<Table, DbError> {
-        let raw = file::read(&self.tbl_path).unwrap_or(Vec::new());
-
-        let mut lines = vec![];
-        if raw.len() > 0 {
-            lines = reader::read(&raw)?;
-        }
-
-        Table::new(&self.tbl_name, lines)
+    fn line_to_str(line: &Line) -> String {
+        line_to_str(line)
     }
 
-    fn is_of_type(base_path: &str, tbl: &str) -> bool {
-        let of_type = match TableManagerV1::get_fullpath(base_path, tbl) {
-            Ok(p) => {
-                let mut is_v1 = false;
-                let contents = file::read(&p).unwrap_or(Vec::new());
-                if contents.len() > 0 {
-                    is_v1 = contents[0] == TBL_VERSION;
-                }
-
-                is_v1
-            }
-            Err(_) => false,
-        };
-
-        of_type
+    fn parse(raw: &[String]) -> Result<Vec<Line>, DbError> {
+        reader::read(raw)
     }
-}
-
-impl TableManagerV1 {
-    pub fn new(base_path: &str, tbl: &str) -> Result<TableManagerV1, DbError> {
-        let fullpath = TableManagerV1::get_fullpath(base_path, tbl)?;
 
-        let m = TableManagerV1 {
-            tbl_path: String::from(fullpath),
-            tbl_name: String::from(tbl),
-        };
-
-        Ok(m)
+    fn entry_to_str(entry: &Entry) -> String {
+        entry_to_str(entry)
     }
 
-    fn get_fullpath(base_path: &str, tbl: &str) -> Result<String, DbError> {
-        let with_ext = tbl.to_owned();
-        let fullpath = std::path::Path::new(base_path).join(with_ext);
-        let fullpath = match fullpath.to_str() {
-            Some(p) => p,
-            None => {
-                return Err(DbError::Custom(String::from(
-                    "The path to the table is empty",
-                )))
-            }
-        };
-
-        Ok(String::from(fullpath))
+    fn read_journal(raw: &[String]) -> Result<Vec<Entry>, DbError> {
+        reader::read_journal(raw)
     }
 
-    fn convert_to_str(lines: &Vec<&Line>) -> Vec<String> {
-        let mut str_lines: Vec<String> = Vec::new();
-        for line in lines {
-            str_lines.push(line_to_str(&line));
-        }
-
-        str_lines
+    fn in_value(inside: bool, line: &str) -> bool {
+        reader::in_value(inside, line)
     }
 }
 
@@ -199,6 +147,136 @@ fn test_read() {
     m.drop().unwrap();
 }
 
+#[test]
+fn test_compact() {
+    let mut m = TableManagerV1::new_with_options("/tmp", "test_compact_tbl", &Options { journal: true, ..Options::default() }).unwrap();
+    m.drop().unwrap();
+    assert_eq!(m.compact().unwrap(), 0);
+
+    _insert(&mut m);
+    _insert(&mut m);
+    _insert(&mut m);
+
+    let mut table = m.read().unwrap();
+    let id = *table.get_lines()[0].get_id();
+    table.delete(&id);
+    m.write(&mut table, false).unwrap();
+    assert_eq!(_count_lines(&m.tbl_path), 2);
+    assert!(_count_lines(&m.journal_path) > 0);
+
+    let size = file::size(&m.tbl_path) + file::size(&m.journal_path);
+    let reclaimed = m.compact().unwrap();
+    assert!(reclaimed > 0);
+    assert_eq!(file::size(&m.tbl_path), size - reclaimed);
+    assert_eq!(_count_lines(&m.tbl_path), 3);
+    assert_eq!(_count_lines(&m.journal_path), 0);
+    assert_eq!(m.read().unwrap().get_lines().len(), 2);
+
+    // Nothing left to reclaim
+    assert_eq!(m.compact().unwrap(), 0);
+
+    m.drop().unwrap();
+}
+
+#[test]
+fn test_write_changes_only() {
+    let mut m = TableManagerV1::new_with_options("/tmp", "test_write_changes_tbl", &Options { journal: true, ..Options::default() }).unwrap();
+    m.drop().unwrap();
+
+    _insert(&mut m);
+    _insert(&mut m);
+
+    // Only the inserted line is appended to the journal
+    let journal = std::fs::read_to_string(&m.journal_path).unwrap();
+    assert_eq!(journal.lines().filter(|l| l.starts_with('+')).count(), 1);
+
+    // Nothing changed, nothing is written
+    let mut table = m.read().unwrap();
+    let size = file::size(&m.journal_path);
+    m.write(&mut table, false).unwrap();
+    assert_eq!(file::size(&m.journal_path), size);
+
+    // The changes are reset after a write
+    let id = *table.get_lines()[0].get_id();
+    table.delete(&id);
+    m.write(&mut table, false).unwrap();
+    assert!(table.changes().is_empty());
+    assert_eq!(m.read().unwrap().get_lines().len(), 1);
+
+    m.drop().unwrap();
+}
+
+#[test]
+fn test_write_special_values() {
+    let values = ["Using \" in a text", "Hélène Bérubé: «crème brûlée»", "\"\"", "Two\nlines\r\n", "\n[_id:\"x\"]\n#\n", " ", ""];
+
+    for journal in [false, true] {
+        let mut m = TableManagerV1::new_with_options("/tmp", "test_special_values_tbl", &Options { journal, ..Options::default() }).unwrap();
+        m.drop().unwrap();
+        _insert(&mut m);
+
+        let mut table = m.read().unwrap();
+        for value in values {
+            let mut line = Line::new();
+            line.add("col1", Type::String(String::from(value))).unwrap();
+            table.insert(line);
+        }
+        m.write(&mut table, false).unwrap();
+
+        let read: Vec<String> = m.read().unwrap().get_lines().iter().skip(1).map(|l| l.get("col1").unwrap().get().to_string()).collect();
+        assert_eq!(read, values);
+
+        m.drop().unwrap();
+    }
+}
+
+#[test]
+fn test_write_invalid_name() {
+    for journal in [false, true] {
+        let mut m = TableManagerV1::new_with_options("/tmp", "test_invalid_name_tbl", &Options { journal, ..Options::default() }).unwrap();
+        m.drop().unwrap();
+        _insert(&mut m);
+
+        // Line::add rejects the name, but a Field can still be built with it
+        let mut table = m.read().unwrap();
+        table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_str("first name", "Mike")]));
+        assert!(m.write(&mut table, false).is_err());
+        assert_eq!(m.read().unwrap().get_lines().len(), 1);
+
+        m.drop().unwrap();
+    }
+}
+
+#[test]
+fn test_interrupted_append_with_line_breaks() {
+    let mut m = TableManagerV1::new_with_options("/tmp", "test_torn_append_tbl", &Options { journal: true, ..Options::default() }).unwrap();
+    m.drop().unwrap();
+    _insert(&mut m);
+
+    let mut table = m.read().unwrap();
+    table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_str("col1", "a\n#\nb")]));
+    m.write(&mut table, false).unwrap();
+    let committed = std::fs::read_to_string(&m.journal_path).unwrap();
+
+    // The process dies right after the "#" inside the value of the next batch
+    table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_str("col1", "c\n#\nd")]));
+    m.write(&mut table, false).unwrap();
+    let full = std::fs::read_to_string(&m.journal_path).unwrap();
+    let torn = &full[..committed.len() + full[committed.len()..].find("#\n").unwrap() + 2];
+    std::fs::write(&m.journal_path, torn).unwrap();
+
+    let mut table = m.read().unwrap();
+    assert_eq!(table.get_lines().len(), 2);
+    assert_eq!(table.get_lines()[1].get("col1").unwrap().get().to_string(), "a\n#\nb");
+
+    // The next append removes the torn batch
+    table.insert(Line::new());
+    m.write(&mut table, false).unwrap();
+    assert_eq!(m.read().unwrap().get_lines().len(), 3);
+
+    m.drop().unwrap();
+}
+
 fn _insert(m: &mut TableManagerV1) {
     let mut table = m.read().unwrap();
     let mut line = Line::new();
@@ -206,7 +284,7 @@ fn _insert(m: &mut TableManagerV1) {
 
     table.insert(line);
 
-    assert_eq!(m.write(&mut table).is_ok(), true);
+    assert_eq!(m.write(&mut table, false).is_ok(), true);
 }
 
 fn _count_lines(path: &str) -> usize {
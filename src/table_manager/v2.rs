@@ -0,0 +1,2 @@
+pub mod format;
+pub mod table_manager_v2;
\ No newline at end of file
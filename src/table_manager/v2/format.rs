@@ -0,0 +1,440 @@
+//! The V2 format of the table files
+//!
+//! ```text
+//! #v2.0#
+//! @fields name:string age:integer
+//! @options
+//! 5435c914-a918-4cc7-8354-e55ff04d9e25 name:"Mike" age:"30"
+//! 3b3f4537-1b8b-4577-999f-e650ea76e190 name:"Sean \"The Boss\""
+//! ```
+//!
+//! The header starts with the version. It is followed by the catalog of the
+//! fields (the name and the type of every field used by the lines, in the order
+//! they first appear) and by the options of the table (`key=value`, separated
+//! by spaces). When the table is rewritten, the fields of the catalog keep their
+//! type and their place as long as a line uses them, so a change to a line does
+//! not change the other lines.
+//!
+//! Then every line of the table is on its own line of the file, so a change to
+//! a line is a change to a single line of the file. The backslashes, the quotes
+//! and the line breaks of the values are escaped. A field only has its type when
+//! it differs from the catalog (the same field can hold different types on
+//! different lines).
+use std::collections::{BTreeMap, HashMap};
+
+use uuid::Uuid;
+
+use crate::db::{db_error::DbError, field::Field, field_type::Type, line::Line};
+use crate::table_manager::journal::{self, Entry};
+
+/// First line of a V2 table file
+pub const TBL_VERSION: &str = "#v2.0#";
+
+/// Start of the line with the catalog of the fields
+const FIELDS: &str = "@fields";
+
+/// Start of the line with the options of the table
+const OPTIONS: &str = "@options";
+
+/**
+ * The header of a V2 table file (without the version)
+ */
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Default)]
+pub struct Header {
+    /**
+     * The name and the type of the fields, in the order they first appear
+     */
+    pub fields: Vec<(String, String)>,
+    /**
+     * The options of the table. The unknown options are kept when the table is rewritten
+     */
+    pub options: BTreeMap<String, String>
+}
+
+impl Header {
+    /**
+     * Build the catalog of the fields used by the lines
+     *
+     * The fields of the previous header that are still used are kept first, with
+     * their type. The options of the previous header are kept.
+     */
+    pub fn new(lines: &[&Line], previous: &Header) -> Header {
+        let mut fields: Vec<(String, String)> = previous.fields.iter()
+            .filter(|(name, _)| lines.iter().any(|line| line.get(name).is_some()))
+            .cloned()
+            .collect();
+        for line in lines {
+            for field in line.get_fields() {
+                if !fields.iter().any(|(name, _)| name == field.get_name()) {
+                    fields.push((String::from(field.get_name()), field.get().get_type()));
+                }
+            }
+        }
+
+        Header { fields, options: previous.options.clone() }
+    }
+
+    fn catalog(&self) -> HashMap<&str, &str> {
+        self.fields.iter().map(|(name, t)| (name.as_str(), t.as_str())).collect()
+    }
+}
+
+/**
+ * Convert the lines to the content of the file, without the version (see file::write_tmp)
+ *
+ * `previous` is the header of the file being rewritten (see Header::new)
+ */
+pub fn lines_to_str(lines: &[&Line], previous: &Header) -> Vec<String> {
+    let header = Header::new(lines, previous);
+    let catalog = header.catalog();
+
+    let mut fields = String::from(FIELDS);
+    for (name, t) in &header.fields {
+        fields.push(' ');
+        fields.push_str(name);
+        fields.push(':');
+        fields.push_str(t);
+    }
+
+    let mut opts = String::from(OPTIONS);
+    for (key, value) in &header.options {
+        opts.push(' ');
+        opts.push_str(key);
+        opts.push('=');
+        opts.push_str(value);
+    }
+
+    let mut content = vec![fields, opts];
+    content.extend(lines.iter().map(|l| line_to_str(l, &catalog)));
+
+    content
+}
+
+/**
+ * Convert a line to a line of the file
+ *
+ * The type of a field is only written when it differs from the catalog. With an
+ * empty catalog, the line does not depend on the header (see entry_to_str).
+ */
+pub fn line_to_str(line: &Line, catalog: &HashMap<&str, &str>) -> String {
+    let mut text = line.get_id().to_hyphenated().to_string();
+
+    for field in line.get_fields() {
+        let t = field.get().get_type();
+
+        text.push(' ');
+        text.push_str(field.get_name());
+        text.push(':');
+        if catalog.get(field.get_name()) != Some(&t.as_str()) {
+            text.push_str(&t);
+            text.push(':');
+        }
+        append_value(&mut text, &field.get().to_string());
+    }
+
+    text
+}
+
+/**
+ * Convert a journal Entry to a line of the journal
+ */
+pub fn entry_to_str(entry: &Entry) -> String {
+    match entry {
+        Entry::Upsert(line) => String::from("+") + &line_to_str(line, &HashMap::new()),
+        Entry::Delete(id) => String::from("-") + &id.to_hyphenated().to_string()
+    }
+}
+
+/**
+ * Parse the content of the file (with the version)
+ */
+pub fn read(raw: &[String]) -> Result<(Header, Vec<Line>), DbError> {
+    check_version(raw)?;
+
+    let mut header = Header::default();
+    let mut lines = Vec::new();
+    for text in raw.iter().skip(1).map(|text| strip_cr(text)) {
+        if let Some(fields) = text.strip_prefix(FIELDS) {
+            header.fields = parse_pairs(fields, ':')?;
+        }
+        else if let Some(options) = text.strip_prefix(OPTIONS) {
+            header.options = parse_pairs(options, '=')?.into_iter().collect();
+        }
+        else if !text.is_empty() {
+            lines.push(str_to_line(text, &header.catalog())?);
+        }
+    }
+
+    Ok((header, lines))
+}
+
+/**
+ * Parse the lines of the journal (with the version, see journal::read)
+ */
+pub fn read_journal(raw: &[String]) -> Result<Vec<Entry>, DbError> {
+    check_version(raw)?;
+
+    let mut entries = Vec::new();
+    let mut batch: Vec<Entry> = Vec::new();
+    for text in raw.iter().skip(1).map(|text| strip_cr(text)) {
+        if let Some(line) = text.strip_prefix('+') {
+            batch.push(Entry::Upsert(str_to_line(line, &HashMap::new())?));
+        }
+        else if let Some(id) = text.strip_prefix('-') {
+            batch.push(Entry::Delete(parse_id(id)?));
+        }
+        else if text == journal::BATCH_END {
+            entries.append(&mut batch);
+        }
+        else if !text.is_empty() {
+            return Err(DbError::Custom(String::from("Unexpected journal entry! [") + text + "]"));
+        }
+    }
+
+    if !batch.is_empty() {
+        return Err(DbError::Custom(String::from("The last batch of the journal is incomplete")));
+    }
+
+    Ok(entries)
+}
+
+/**
+ * Tells if a line of the journal ends inside a value (see journal::InValue)
+ *
+ * The line breaks of the values are escaped, a line never ends inside a value
+ */
+pub fn in_value(_inside: bool, _line: &str) -> bool {
+    false
+}
+
+/**
+ * Remove the carriage return of a line (the file was checked out with CRLF line endings)
+ *
+ * The carriage returns of the values are escaped, a line never ends with one of them
+ */
+fn strip_cr(text: &str) -> &str {
+    text.strip_suffix('\r').unwrap_or(text)
+}
+
+fn check_version(raw: &[String]) -> Result<(), DbError> {
+    match raw.first().map(|version| strip_cr(version)) {
+        Some(version) if version == TBL_VERSION => Ok(()),
+        version => {
+            let msg = String::from("Expected [") + TBL_VERSION + "], but received [" + version.unwrap_or("") + "]";
+            Err(DbError::Custom(msg))
+        }
+    }
+}
+
+fn str_to_line(text: &str, catalog: &HashMap<&str, &str>) -> Result<Line, DbError> {
+    let (id, mut rest) = text.split_once(' ').unwrap_or((text, ""));
+    let id = parse_id(id)?;
+
+    let mut fields = Vec::new();
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once(':')
+            .ok_or_else(|| DbError::Custom(String::from("Expected [:] after the field [") + rest + "]"))?;
+
+        let (type_name, after) = if after.starts_with('"') {
+            match catalog.get(name) {
+                Some(t) => (*t, after),
+                None => return Err(DbError::Custom(String::from("The field [") + name + "] is not in the catalog"))
+            }
+        }
+        else {
+            after.split_once(':')
+                .ok_or_else(|| DbError::Custom(String::from("Expected [:] after the type of the field [") + name + "]"))?
+        };
+
+        let (value, after) = read_value(after)?;
+        fields.push(Field::new(name, Type::parse(type_name, &value)?));
+
+        rest = match after.strip_prefix(' ') {
+            Some(next) => next,
+            None if after.is_empty() => after,
+            None => return Err(DbError::Custom(String::from("Expected [ ], but received [") + after + "]"))
+        };
+    }
+
+    Ok(Line::new_with_id(id, fields))
+}
+
+fn parse_id(id: &str) -> Result<Uuid, DbError> {
+    Uuid::parse_str(id).map_err(|error| DbError::Custom(error.to_string()))
+}
+
+/**
+ * Parse the pairs of the header, like `name:string age:integer`
+ */
+fn parse_pairs(text: &str, separator: char) -> Result<Vec<(String, String)>, DbError> {
+    let mut pairs = Vec::new();
+    for pair in text.split_whitespace() {
+        match pair.split_once(separator) {
+            Some((key, value)) => pairs.push((String::from(key), String::from(value))),
+            None => return Err(DbError::Custom(String::from("Invalid header entry [") + pair + "]"))
+        }
+    }
+
+    Ok(pairs)
+}
+
+fn append_value(text: &mut String, value: &str) {
+    text.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => text.push_str("\\\\"),
+            '"' => text.push_str("\\\""),
+            '\n' => text.push_str("\\n"),
+            '\r' => text.push_str("\\r"),
+            c => text.push(c)
+        }
+    }
+    text.push('"');
+}
+
+/**
+ * Read a quoted value, returns the value and what follows it
+ */
+fn read_value(text: &str) -> Result<(String, &str), DbError> {
+    let quoted = text.strip_prefix('"')
+        .ok_or_else(|| DbError::Custom(String::from("Expected [\"], but received [") + text + "]"))?;
+
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &quoted[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, '"')) => value.push('"'),
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 'r')) => value.push('\r'),
+                _ => return Err(DbError::Custom(String::from("Invalid escape in the value [") + quoted + "]"))
+            },
+            c => value.push(c)
+        }
+    }
+
+    Err(DbError::Custom(String::from("Could not find the end of the value [") + &value + "]"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+    use uuid::Uuid;
+
+    use crate::db::{db_error::DbError, field::Field, line::Line};
+    use crate::table_manager::journal::Entry;
+
+    use super::*;
+
+    #[test]
+    fn test_lines_to_str() {
+        let id1 = Uuid::parse_str("5435c914-a918-4cc7-8354-e55ff04d9e25").unwrap();
+        let id2 = Uuid::parse_str("3b3f4537-1b8b-4577-999f-e650ea76e190").unwrap();
+        let line1 = Line::new_with_id(id1, vec![Field::new_str("name", "Mike"), Field::new_int("age", 30)]);
+        let line2 = Line::new_with_id(id2, vec![Field::new_str("name", "Sean \"The Boss\"\nSmith"), Field::new_str("age", "unknown")]);
+        let options = BTreeMap::from([(String::from("sort"), String::from("name"))]);
+
+        let content = lines_to_str(&[&line1, &line2], &Header { options: options.clone(), ..Header::default() });
+        assert_eq!(content, vec![
+            "@fields name:string age:integer",
+            "@options sort=name",
+            "5435c914-a918-4cc7-8354-e55ff04d9e25 name:\"Mike\" age:\"30\"",
+            "3b3f4537-1b8b-4577-999f-e650ea76e190 name:\"Sean \\\"The Boss\\\"\\nSmith\" age:string:\"unknown\"",
+        ]);
+
+        let (header, lines) = read(&[vec![String::from(TBL_VERSION)], content].concat()).unwrap();
+        assert_eq!(header.fields, vec![(String::from("name"), String::from("string")), (String::from("age"), String::from("integer"))]);
+        assert_eq!(header.options, options);
+        assert_eq!(lines, vec![line1, line2]);
+    }
+
+    #[test]
+    fn test_keep_catalog() {
+        let first = Line::new_with_id(Uuid::new_v4(), vec![Field::new_int("age", 30)]);
+        let second = Line::new_with_id(Uuid::new_v4(), vec![Field::new_str("name", "Mike"), Field::new_str("age", "unknown")]);
+        let content = lines_to_str(&[&first, &second], &Header::default());
+        let (header, _) = read(&[vec![String::from(TBL_VERSION)], content.clone()].concat()).unwrap();
+
+        // The first line does not decide the catalog once the file exists
+        let rewritten = lines_to_str(&[&second], &header);
+        assert_eq!(rewritten[0], "@fields age:integer name:string");
+        assert_eq!(rewritten[2], content[3]);
+
+        // The fields no longer used are removed
+        let third = Line::new_with_id(Uuid::new_v4(), vec![Field::new_str("name", "Sean")]);
+        assert_eq!(lines_to_str(&[&third], &header)[0], "@fields name:string");
+    }
+
+    #[test]
+    fn test_read_journal() {
+        let id = Uuid::new_v4();
+        let entries = vec![Entry::Upsert(Line::new_with_id(id, vec![Field::new_int("age", 30)])), Entry::Delete(id)];
+
+        let mut raw = vec![String::from(TBL_VERSION)];
+        raw.extend(entries.iter().map(entry_to_str));
+        raw.push(String::from("#"));
+        assert_eq!(raw[1], String::from("+") + &id.to_string() + " age:integer:\"30\"");
+        assert_eq!(read_journal(&raw).unwrap(), entries);
+
+        // A batch must be closed
+        raw.pop();
+        assert!(read_journal(&raw).is_err());
+    }
+
+    #[test]
+    fn test_crlf() {
+        let line = Line::new_with_id(Uuid::new_v4(), vec![Field::new_str("name", "Mike\r\n")]);
+        let content = [vec![String::from(TBL_VERSION)], lines_to_str(&[&line], &Header::default())].concat();
+        let crlf: Vec<String> = content.iter().map(|text| text.clone() + "\r").collect();
+        assert_eq!(read(&crlf).unwrap(), read(&content).unwrap());
+
+        let journal = vec![String::from(TBL_VERSION) + "\r", String::from("+") + &line_to_str(&line, &HashMap::new()) + "\r", String::from("#\r")];
+        assert_eq!(read_journal(&journal).unwrap(), vec![Entry::Upsert(line)]);
+    }
+
+    #[test]
+    fn test_invalid_format() {
+        let id = "5435c914-a918-4cc7-8354-e55ff04d9e25";
+        let read_line = |text: &str| read(&[String::from(TBL_VERSION), String::from("@fields name:string"), String::from(text)]);
+
+        assert_eq!(_unwrap_custom_error(read(&[String::from("#v1.0#")]).unwrap_err()), "Expected [#v2.0#], but received [#v1.0#]");
+        assert!(read_line("not-an-id").is_err());
+        assert_eq!(_unwrap_custom_error(read_line(&(String::from(id) + " age:\"30\"")).unwrap_err()), "The field [age] is not in the catalog");
+        assert_eq!(_unwrap_custom_error(read_line(&(String::from(id) + " name:\"Mike")).unwrap_err()), "Could not find the end of the value [Mike]");
+        assert_eq!(_unwrap_custom_error(read_line(&(String::from(id) + " name:Mike")).unwrap_err()), "Expected [:] after the type of the field [name]");
+        assert_eq!(_unwrap_custom_error(read_line(&(String::from(id) + " name:\"Mike\"age:\"30\"")).unwrap_err()), "Expected [ ], but received [age:\"30\"]");
+        assert_eq!(_unwrap_custom_error(read_line(&(String::from(id) + " name:\"Mi\\ke\"")).unwrap_err()), "Invalid escape in the value [Mi\\ke\"]");
+        assert_eq!(_unwrap_custom_error(read_line(&(String::from(id) + " name:text:\"Mike\"")).unwrap_err()), "The type [text] is not supported");
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(values in prop::collection::vec(any::<String>(), 0..5), number in any::<i64>()) {
+            let mut fields: Vec<Field> = values.iter().enumerate().map(|(i, v)| Field::new_str(&format!("col{}", i), v)).collect();
+            fields.push(Field::new_int("number", number));
+            let line = Line::new_with_id(Uuid::new_v4(), fields);
+            let other = Line::new_with_id(Uuid::new_v4(), vec![Field::new_int("col0", number)]);
+
+            let content = lines_to_str(&[&line, &other], &Header::default());
+            // One line of the table per line of the file
+            prop_assert_eq!(content.len(), 4);
+            prop_assert!(content.iter().all(|l| !l.contains('\n')));
+
+            let raw: Vec<String> = (String::from(TBL_VERSION) + "\n" + &content.join("\n")).split_terminator('\n').map(String::from).collect();
+            prop_assert_eq!(read(&raw).unwrap().1, vec![line, other]);
+        }
+    }
+
+    fn _unwrap_custom_error(error: DbError) -> String {
+        match error {
+            DbError::Custom(msg) => msg,
+            _ => String::new()
+        }
+    }
+}
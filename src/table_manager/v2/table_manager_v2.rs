@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use crate::db::db_error::DbError;
+use crate::db::line::Line;
+use crate::table_manager::file_manager::{FileTableManager, Format};
+use crate::table_manager::journal::Entry;
+
+use super::format::{self, Header, TBL_VERSION};
+
+/**
+ * The V2 format (see v2::format)
+ */
+pub struct FormatV2;
+
+/**
+ * Manages the tables with the V2 format
+ */
+pub type TableManagerV2 = FileTableManager<FormatV2>;
+
+impl Format for FormatV2 {
+    const VERSION: &'static str = TBL_VERSION;
+    // The carriage returns of the values are escaped
+    const STRIP_CR: bool = true;
+
+    fn lines_to_str(lines: &[&Line], previous: Option<&[String]>) -> Result<Vec<String>, DbError> {
+        // The catalog and the options of the table are kept
+        let header = match previous {
+            Some(raw) => format::read(raw)?.0,
+            None => Header::default()
+        };
+
+        Ok(format::lines_to_str(lines, &header))
+    }
+
+    /**
+     * The line has the type of every field, it does not depend on the catalog
+     */
+    fn line_to_str(line: &Line) -> String {
+        format::line_to_str(line, &HashMap::new())
+    }
+
+    fn parse(raw: &[String]) -> Result<Vec<Line>, DbError> {
+        Ok(format::read(raw)?.1)
+    }
+
+    fn entry_to_str(entry: &Entry) -> String {
+        format::entry_to_str(entry)
+    }
+
+    fn read_journal(raw: &[String]) -> Result<Vec<Entry>, DbError> {
+        format::read_journal(raw)
+    }
+
+    fn in_value(inside: bool, line: &str) -> bool {
+        format::in_value(inside, line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::db::{field::Field, line::Line};
+    use crate::table_manager::{Options, TableManager};
+    use crate::util::file;
+
+    use super::{format, Header, TableManagerV2, TBL_VERSION};
+
+    #[test]
+    fn test_is_of_type() {
+        let path = "/tmp/test_of_type_v2_tbl";
+
+        file::remove_file(path).unwrap();
+        assert!(!TableManagerV2::is_of_type("/tmp", "test_of_type_v2_tbl"));
+
+        file::write(path, "#v1.0#", &[]).unwrap();
+        assert!(!TableManagerV2::is_of_type("/tmp", "test_of_type_v2_tbl"));
+
+        let m = TableManagerV2::new("/tmp", "test_of_type_v2_tbl").unwrap();
+        m.drop().unwrap();
+        m.create().unwrap();
+        assert!(TableManagerV2::is_of_type("/tmp", "test_of_type_v2_tbl"));
+        assert_eq!(file::read(path).unwrap(), vec![TBL_VERSION, "@fields", "@options"]);
+
+        m.drop().unwrap();
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        for journal in [false, true] {
+            let mut m = TableManagerV2::new_with_options("/tmp", "test_write_v2_tbl", &Options { journal, ..Options::default() }).unwrap();
+            m.drop().unwrap();
+            m.create().unwrap();
+
+            let mut table = m.read().unwrap();
+            table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_str("name", "Hélène\n\"Bérubé\""), Field::new_int("age", 30)]));
+            table.insert(Line::new());
+            m.write(&mut table, false).unwrap();
+
+            let mut table = m.read().unwrap();
+            assert_eq!(table.get_lines().len(), 2);
+            assert_eq!(table.get_lines()[0].get("name").unwrap().get().to_string(), "Hélène\n\"Bérubé\"");
+
+            let id = *table.get_lines()[1].get_id();
+            table.delete(&id);
+            m.write(&mut table, false).unwrap();
+            assert_eq!(m.read().unwrap().get_lines().len(), 1);
+
+            // The journal is folded into the table file
+            m.compact().unwrap();
+            assert_eq!(file::read(&m.tbl_path).unwrap().len(), 4);
+            assert_eq!(m.read().unwrap().get_lines().len(), 1);
+
+            m.drop().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_keep_options() {
+        let mut m = TableManagerV2::new("/tmp", "test_options_v2_tbl").unwrap();
+        m.drop().unwrap();
+
+        let options = BTreeMap::from([(String::from("sort"), String::from("name"))]);
+        file::write(&m.tbl_path, TBL_VERSION, &format::lines_to_str(&[], &Header { options, ..Header::default() })).unwrap();
+
+        let mut table = m.read().unwrap();
+        table.insert(Line::new());
+        m.write(&mut table, false).unwrap();
+        assert_eq!(file::read(&m.tbl_path).unwrap()[2], "@options sort=name");
+
+        m.drop().unwrap();
+    }
+
+    #[test]
+    fn test_delete_first_line() {
+        let mut m = TableManagerV2::new("/tmp", "test_delete_first_v2_tbl").unwrap();
+        m.drop().unwrap();
+        m.create().unwrap();
+
+        let mut table = m.read().unwrap();
+        table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_int("age", 30)]));
+        table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_str("age", "unknown")]));
+        table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_int("age", 12)]));
+        m.write(&mut table, false).unwrap();
+        let before = file::read(&m.tbl_path).unwrap();
+
+        // The type of the catalog does not follow the new first line
+        let id = *table.get_lines()[0].get_id();
+        table.delete(&id);
+        m.write(&mut table, false).unwrap();
+        let after = file::read(&m.tbl_path).unwrap();
+        assert_eq!(after[1], before[1]);
+        assert_eq!(after[3..], before[4..]);
+
+        m.drop().unwrap();
+    }
+
+    #[test]
+    fn test_crlf() {
+        let mut m = TableManagerV2::new("/tmp", "test_crlf_v2_tbl").unwrap();
+        m.drop().unwrap();
+        m.create().unwrap();
+
+        let mut table = m.read().unwrap();
+        table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_str("name", "Mike")]));
+        m.write(&mut table, false).unwrap();
+
+        // The table was checked out with CRLF line endings
+        let crlf = std::fs::read_to_string(&m.tbl_path).unwrap().replace('\n', "\r\n");
+        std::fs::write(&m.tbl_path, crlf).unwrap();
+        assert!(TableManagerV2::is_of_type("/tmp", "test_crlf_v2_tbl"));
+        let table = m.read().unwrap();
+        assert_eq!(table.get_lines()[0].get("name").unwrap().get().to_string(), "Mike");
+
+        m.drop().unwrap();
+    }
+
+    #[test]
+    fn test_crlf_with_journal() {
+        let mut m = TableManagerV2::new_with_options("/tmp", "test_crlf_journal_v2_tbl", &Options { journal: true, ..Options::default() }).unwrap();
+        m.drop().unwrap();
+        m.create().unwrap();
+
+        let mut table = m.read().unwrap();
+        for name in ["Mike", "Sean", "Simon"] {
+            table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_str("name", name)]));
+            m.write(&mut table, false).unwrap();
+        }
+        assert!(file::size(&m.journal_path) > 0);
+
+        // Both files were checked out with CRLF line endings
+        for path in [&m.tbl_path, &m.journal_path] {
+            let crlf = std::fs::read_to_string(path).unwrap().replace('\n', "\r\n");
+            std::fs::write(path, crlf).unwrap();
+        }
+        let mut table = m.read().unwrap();
+        assert_eq!(table.get_lines().len(), 3);
+
+        // The journal is still appended to
+        table.insert(Line::new_with_id(uuid::Uuid::new_v4(), vec![Field::new_str("name", "Mark")]));
+        m.write(&mut table, false).unwrap();
+        assert_eq!(m.read().unwrap().get_lines().len(), 4);
+
+        m.drop().unwrap();
+    }
+}
@@ -1,39 +1,140 @@
-
-use std::fs::{OpenOptions, File};
-use std::io::{BufReader, prelude::*};
+use std::fs::File;
+use std::io::{BufWriter, prelude::*};
 use std::path::Path;
 use std::io;
 
 use crate::db::db_error::DbError;
 
+/**
+ * Read the lines of a file
+ *
+ * The lines are only split on \n, a \r is kept since it can be part of a value
+ */
 pub fn read(path: &str) -> Result<Vec<String>, DbError>  {
-    let file = File::open(path)?;
+    let text = std::fs::read_to_string(path)?;
 
-    let buf = BufReader::new(file);
-    Ok(buf.lines().map(|l| l.expect("Cannot parse line")).collect())
+    Ok(text.split_terminator('\n').map(String::from).collect())
 }
 
-pub fn insert(path: &str, line: &str) -> Result<(), io::Error> {
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path)?;
+/// Offset basis of the 64 bits FNV-1a hash (see checksum)
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+/// Prime of the 64 bits FNV-1a hash (see checksum)
+const FNV_PRIME: u64 = 0x100000001b3;
 
-    writeln!(file, "{}", line)?;
+/**
+ * Compute a token that changes whenever the lines read from a file change
+ *
+ * It is written to the files (e.g. the journals), so it must not depend on the
+ * build: it is the 64 bits FNV-1a hash of the lines, each followed by \n, in hexadecimal
+ */
+pub fn checksum(lines: &[String]) -> String {
+    let mut hash = FNV_OFFSET;
+    for byte in lines.iter().flat_map(|line| line.bytes().chain(std::iter::once(b'\n'))) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
 
-    Ok(())
+    format!("{:016x}", hash)
+}
+
+/**
+ * Atomically replace the content of a file
+ *
+ * The new content is first written to a temporary file next to the original one.
+ * Once it is safely on disk, the temporary file is renamed over the original one.
+ * The file is always either the old version or the new version, never a mix of both.
+ */
+pub fn write(path: &str, version: &str, lines: &[String]) -> Result<bool, io::Error> {
+    let file_created = !Path::new(path).exists();
+
+    write_tmp(path, version, lines)?;
+    commit_tmp(path)?;
+
+    Ok(file_created)
 }
 
-pub fn write(path: &str, version: &str, lines: &Vec<String>) -> Result<bool, io::Error> {
-    let file_existed = remove_file(path)?;
-    let file_created = !file_existed;
+/**
+ * Write the content to the temporary file of `path` and flush it to the disk
+ */
+pub fn write_tmp(path: &str, version: &str, lines: &[String]) -> Result<(), io::Error> {
+    let file = File::create(tmp_path(path))?;
 
-    insert(path, version)?;
+    let mut buf = BufWriter::new(file);
+    writeln!(buf, "{}", version)?;
     for line in lines {
-        insert(path, line)?;
+        writeln!(buf, "{}", line)?;
     }
 
-    Ok(file_created)
+    let file = buf.into_inner()?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/**
+ * Replace the file with its temporary file (see write_tmp)
+ */
+pub fn commit_tmp(path: &str) -> Result<(), io::Error> {
+    std::fs::rename(tmp_path(path), path)?;
+    sync_parent(path)?;
+
+    Ok(())
+}
+
+/**
+ * Size of the file in bytes (0 if it does not exist)
+ */
+pub fn size(path: &str) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/**
+ * Path of the temporary file used while writing `path`
+ *
+ * The name starts with a "." so it is never listed as a table
+ */
+pub fn tmp_path(path: &str) -> String {
+    sibling_path(path, ".tmp")
+}
+
+/**
+ * Path of the lock file used to share `path` between processes
+ */
+pub fn lock_path(path: &str) -> String {
+    sibling_path(path, ".lock")
+}
+
+/**
+ * Path of the journal where the changes to `path` are appended
+ */
+pub fn journal_path(path: &str) -> String {
+    sibling_path(path, ".journal")
+}
+
+fn sibling_path(path: &str, ext: &str) -> String {
+    let p = Path::new(path);
+    let name = match p.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return String::from(path) + ext
+    };
+
+    let sibling = String::from(".") + &name + ext;
+    p.with_file_name(sibling).to_string_lossy().into_owned()
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &str) -> Result<(), io::Error> {
+    if let Some(parent) = Path::new(path).parent() {
+        let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &str) -> Result<(), io::Error> {
+    Ok(())
 }
 
 pub fn remove_file(s_path: &str) -> Result<bool, io::Error> {
@@ -47,3 +148,73 @@ pub fn remove_file(s_path: &str) -> Result<bool, io::Error> {
     Ok(file_existed)
 }
 
+#[test]
+fn test_tmp_path() {
+    assert_eq!(tmp_path("/tmp/db/table"), "/tmp/db/.table.tmp");
+    assert_eq!(tmp_path("/tmp/db/.config"), "/tmp/db/..config.tmp");
+    assert_eq!(tmp_path("table"), ".table.tmp");
+    assert_eq!(lock_path("/tmp/db/table"), "/tmp/db/.table.lock");
+    assert_eq!(journal_path("/tmp/db/table"), "/tmp/db/.table.journal");
+}
+
+#[test]
+fn test_checksum() {
+    let v1 = checksum(&["#v1.0#".to_owned(), "line1".to_owned()]);
+
+    assert_eq!(checksum(&["#v1.0#".to_owned(), "line1".to_owned()]), v1);
+    assert_ne!(checksum(&["#v1.0#".to_owned(), "line2".to_owned()]), v1);
+    assert_ne!(checksum(&["#v1.0#line1".to_owned()]), v1);
+
+    // The value is written to the files, it must never change
+    assert_eq!(checksum(&[]), "cbf29ce484222325");
+    assert_eq!(v1, "ebc2d116b1fd083d");
+}
+
+#[test]
+fn test_write() {
+    let path = "/tmp/test_file_write";
+    remove_file(path).unwrap();
+
+    let created = write(path, "#v1.0#", &["line1".to_owned()]).unwrap();
+    assert!(created);
+    assert_eq!(read(path).unwrap(), vec!["#v1.0#", "line1"]);
+
+    let created = write(path, "#v1.0#", &["line1".to_owned(), "line2".to_owned()]).unwrap();
+    assert!(!created);
+    assert_eq!(read(path).unwrap(), vec!["#v1.0#", "line1", "line2"]);
+    assert!(!Path::new(&tmp_path(path)).exists());
+
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn test_interrupted_write_before_rename() {
+    let path = "/tmp/test_file_interrupted_rename";
+    write(path, "#v1.0#", &["old".to_owned()]).unwrap();
+
+    // The process dies after writing the temporary file, but before the rename
+    write_tmp(path, "#v1.0#", &["new".to_owned(), "new".to_owned()]).unwrap();
+    assert_eq!(read(path).unwrap(), vec!["#v1.0#", "old"]);
+
+    // The next write is not bothered by the leftover
+    write(path, "#v1.0#", &["newer".to_owned()]).unwrap();
+    assert_eq!(read(path).unwrap(), vec!["#v1.0#", "newer"]);
+    assert!(!Path::new(&tmp_path(path)).exists());
+
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn test_interrupted_write_partial_tmp() {
+    let path = "/tmp/test_file_interrupted_partial";
+    write(path, "#v1.0#", &["old1".to_owned(), "old2".to_owned()]).unwrap();
+
+    // The process dies in the middle of writing the temporary file
+    std::fs::write(tmp_path(path), "#v1.0#\nne").unwrap();
+    assert_eq!(read(path).unwrap(), vec!["#v1.0#", "old1", "old2"]);
+
+    write(path, "#v1.0#", &["new".to_owned()]).unwrap();
+    assert_eq!(read(path).unwrap(), vec!["#v1.0#", "new"]);
+
+    remove_file(path).unwrap();
+}
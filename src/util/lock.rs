@@ -0,0 +1,104 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::db::db_error::DbError;
+
+use super::file;
+
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/**
+ * Advisory lock on a file, shared between processes
+ *
+ * The lock is taken on a separate ".lock" file, because the file itself is
+ * replaced on every write. It is released when the Lock is dropped.
+ */
+pub struct Lock {
+    _file: File
+}
+
+impl Lock {
+    /**
+     * Take a shared lock (multiple readers are allowed at the same time)
+     */
+    pub fn shared(path: &str, timeout: Duration) -> Result<Lock, DbError> {
+        Lock::acquire(path, timeout, |f| f.try_lock_shared())
+    }
+
+    /**
+     * Take an exclusive lock (no one else can read or write)
+     */
+    pub fn exclusive(path: &str, timeout: Duration) -> Result<Lock, DbError> {
+        Lock::acquire(path, timeout, |f| f.try_lock())
+    }
+
+    fn acquire<F>(path: &str, timeout: Duration, try_lock: F) -> Result<Lock, DbError>
+        where F: Fn(&File) -> Result<(), TryLockError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(file::lock_path(path))?;
+
+        let start = Instant::now();
+        loop {
+            match try_lock(&file) {
+                Ok(()) => return Ok(Lock { _file: file }),
+                Err(TryLockError::Error(error)) => return Err(DbError::IoError(error)),
+                Err(TryLockError::WouldBlock) => {
+                    if start.elapsed() >= timeout {
+                        return Err(DbError::Locked(String::from(path)));
+                    }
+
+                    thread::sleep(RETRY_DELAY);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_shared() {
+    let path = "/tmp/test_lock_shared";
+    let timeout = Duration::from_millis(50);
+
+    let first = Lock::shared(path, timeout).unwrap();
+    let second = Lock::shared(path, timeout).unwrap();
+
+    match Lock::exclusive(path, timeout) {
+        Err(DbError::Locked(p)) => assert_eq!(p, path),
+        _ => panic!("The exclusive lock should not be taken while it is shared")
+    }
+
+    drop(first);
+    drop(second);
+    assert!(Lock::exclusive(path, timeout).is_ok());
+}
+
+#[test]
+fn test_exclusive() {
+    let path = "/tmp/test_lock_exclusive";
+    let timeout = Duration::from_millis(50);
+
+    let lock = Lock::exclusive(path, timeout).unwrap();
+    assert!(matches!(Lock::shared(path, timeout), Err(DbError::Locked(_))));
+    assert!(matches!(Lock::exclusive(path, Duration::ZERO), Err(DbError::Locked(_))));
+
+    drop(lock);
+    assert!(Lock::shared(path, timeout).is_ok());
+}
+
+#[test]
+fn test_wait_for_lock() {
+    let path = "/tmp/test_lock_wait";
+
+    let lock = Lock::exclusive(path, Duration::ZERO).unwrap();
+    let handle = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        drop(lock);
+    });
+
+    assert!(Lock::exclusive(path, Duration::from_secs(5)).is_ok());
+    handle.join().unwrap();
+}
@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::process::Command;
+
+use rental_rod::db::{field_type::Type, line::Line, Db};
+
+#[test]
+fn test_merge_driver() {
+    let p = "/tmp/test_merge_driver";
+    if Path::new(p).exists() {
+        std::fs::remove_dir_all(p).unwrap();
+    }
+
+    let mut db = Db::new(p).unwrap();
+    db.set_use_git(true, None).unwrap();
+    db.set_merge_driver(env!("CARGO_BIN_EXE_rental_rod_merge")).unwrap();
+
+    let mut table = db.table("orders").unwrap();
+    let mut line = Line::new();
+    line.add("col1", Type::from_str("a")).unwrap();
+    line.add("col2", Type::from_str("b")).unwrap();
+    let id = *line.get_id();
+    table.insert(line);
+    db.write(&mut table).unwrap();
+
+    // Both branches change the same line, but not the same field
+    _git(p, &["checkout", "-q", "-b", "other"]);
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col2").unwrap().set(Type::from_str("theirs"));
+    db.write(&mut table).unwrap();
+
+    _git(p, &["checkout", "-q", "-"]);
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col1").unwrap().set(Type::from_str("ours"));
+    db.write(&mut table).unwrap();
+
+    assert!(_git(p, &["merge", "-q", "other", "-m", "Merge other"]));
+    let mut table = db.table("orders").unwrap();
+    let line = table.find_by_id(&id).unwrap();
+    assert_eq!(line.get("col1").unwrap().get().to_string(), "ours");
+    assert_eq!(line.get("col2").unwrap().get().to_string(), "theirs");
+
+    // Both branches change the same field
+    _git(p, &["checkout", "-q", "other"]);
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col2").unwrap().set(Type::from_str("again"));
+    db.write(&mut table).unwrap();
+
+    _git(p, &["checkout", "-q", "-"]);
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&id).unwrap().get_mut("col2").unwrap().set(Type::from_str("conflict"));
+    db.write(&mut table).unwrap();
+
+    assert!(!_git(p, &["merge", "-q", "other", "-m", "Merge other"]));
+    let content = std::fs::read_to_string(String::from(p) + "/orders").unwrap();
+    assert!(content.contains("<<<<<<< ours"));
+    assert!(content.contains(">>>>>>> theirs"));
+}
+
+#[test]
+fn test_merge_driver_journal() {
+    let p = "/tmp/test_merge_driver_journal";
+    if Path::new(p).exists() {
+        std::fs::remove_dir_all(p).unwrap();
+    }
+
+    let mut db = Db::new(p).unwrap();
+    db.set_use_git(true, None).unwrap();
+    db.set_use_journal(true).unwrap();
+    db.set_merge_driver(env!("CARGO_BIN_EXE_rental_rod_merge")).unwrap();
+
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_line());
+    table.insert(_new_line());
+    db.write(&mut table).unwrap();
+    let ids: Vec<_> = table.get_lines().iter().map(|l| *l.get_id()).collect();
+
+    // Both branches only append to the journal
+    _git(p, &["checkout", "-q", "-b", "other"]);
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&ids[0]).unwrap().get_mut("col2").unwrap().set(Type::from_str("theirs"));
+    table.insert(_new_line());
+    db.write(&mut table).unwrap();
+
+    _git(p, &["checkout", "-q", "-"]);
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&ids[0]).unwrap().get_mut("col1").unwrap().set(Type::from_str("ours"));
+    table.delete(&ids[1]);
+    db.write(&mut table).unwrap();
+    assert!(Path::new(p).join(".orders.journal").exists());
+
+    assert!(_git(p, &["merge", "-q", "other", "-m", "Merge other"]));
+    let mut table = db.table("orders").unwrap();
+    assert_eq!(table.get_lines().len(), 2);
+    let line = table.find_by_id(&ids[0]).unwrap();
+    assert_eq!(line.get("col1").unwrap().get().to_string(), "ours");
+    assert_eq!(line.get("col2").unwrap().get().to_string(), "theirs");
+
+    // Both branches compact the table, then append to the journal
+    _git(p, &["checkout", "-q", "other"]);
+    _git(p, &["merge", "-q", "-"]);
+    db.compact("orders").unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.find_by_id(&ids[0]).unwrap().get_mut("col2").unwrap().set(Type::from_str("theirs again"));
+    db.write(&mut table).unwrap();
+
+    _git(p, &["checkout", "-q", "-"]);
+    db.compact("orders").unwrap();
+    let mut table = db.table("orders").unwrap();
+    table.insert(_new_line());
+    table.find_by_id(&ids[0]).unwrap().get_mut("col1").unwrap().set(Type::from_str("ours again"));
+    db.write(&mut table).unwrap();
+
+    assert!(_git(p, &["merge", "-q", "other", "-m", "Merge other"]));
+    let mut table = db.table("orders").unwrap();
+    assert_eq!(table.get_lines().len(), 3);
+    let line = table.find_by_id(&ids[0]).unwrap();
+    assert_eq!(line.get("col1").unwrap().get().to_string(), "ours again");
+    assert_eq!(line.get("col2").unwrap().get().to_string(), "theirs again");
+}
+
+fn _new_line() -> Line {
+    let mut line = Line::new();
+    line.add("col1", Type::from_str("a")).unwrap();
+    line.add("col2", Type::from_str("b")).unwrap();
+
+    line
+}
+
+fn _git(p: &str, args: &[&str]) -> bool {
+    Command::new("git").arg("-C").arg(p).args(args).output().unwrap().status.success()
+}